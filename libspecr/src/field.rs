@@ -0,0 +1,90 @@
+use crate::hidden::{GcCow, GcCompat};
+
+// specr does not know the types of expressions, so it can't tell whether a struct field access `s.f` refers to a field with an
+// `#[specr::indirection]`, which has to be unwrapped. Instead, it translates `s.f` to `(&s.f).__specr_get_field()`,
+// `s.f = v` to `(&mut s.f).__specr_set_field(v)`, and `s.f.x = v` or `s.f += v` to `__specr_mutate_field` calls.
+//
+// The traits for `GcCow` are implemented for references, taking `self` by value;
+// the fallback traits for all other types are implemented for references as well, but take `self` by reference.
+// As method resolution tries the receiver by value before adding another reference,
+// fields of type `GcCow<T>` are unwrapped, and all other fields are used as they are.
+
+/// Reads a field with an indirection.
+pub trait IndirectField<T> {
+    /// specr translates `s.f` to `(&s.f).__specr_get_field()`.
+    fn __specr_get_field(self) -> T;
+}
+
+impl<T: GcCompat + Clone> IndirectField<T> for &GcCow<T> {
+    fn __specr_get_field(self) -> T {
+        self.extract()
+    }
+}
+
+/// Writes a field with an indirection.
+pub trait IndirectFieldMut<T> {
+    /// specr translates `s.f = t` to `(&mut s.f).__specr_set_field(t)`.
+    fn __specr_set_field(self, t: T);
+    /// Calls `f` on the field.
+    /// specr translates `s.f += t` and `s.f.x = t` to `__specr_mutate_field` calls.
+    fn __specr_mutate_field<O>(self, f: impl FnOnce(&mut T) -> O) -> O;
+}
+
+impl<T: GcCompat + Clone> IndirectFieldMut<T> for &mut GcCow<T> {
+    fn __specr_set_field(self, t: T) {
+        *self = GcCow::new(t);
+    }
+
+    fn __specr_mutate_field<O>(self, f: impl FnOnce(&mut T) -> O) -> O {
+        self.mutate(f)
+    }
+}
+
+/// Reads a field without an indirection.
+pub trait PlainField<T> {
+    /// See `IndirectField::__specr_get_field`.
+    fn __specr_get_field(&self) -> T;
+}
+
+impl<T: Clone> PlainField<T> for &T {
+    fn __specr_get_field(&self) -> T {
+        (**self).clone()
+    }
+}
+
+/// Writes a field without an indirection.
+pub trait PlainFieldMut<T> {
+    /// See `IndirectFieldMut::__specr_set_field`.
+    fn __specr_set_field(&mut self, t: T);
+    /// See `IndirectFieldMut::__specr_mutate_field`.
+    fn __specr_mutate_field<O>(&mut self, f: impl FnOnce(&mut T) -> O) -> O;
+}
+
+impl<T> PlainFieldMut<T> for &mut T {
+    fn __specr_set_field(&mut self, t: T) {
+        **self = t;
+    }
+
+    fn __specr_mutate_field<O>(&mut self, f: impl FnOnce(&mut T) -> O) -> O {
+        f(self)
+    }
+}
+
+#[test]
+fn field_test() {
+    use crate::Int;
+
+    struct S {
+        a: GcCow<Int>,
+        b: Int,
+    }
+    let mut s = S { a: GcCow::new(Int::from(1)), b: Int::from(2) };
+    assert_eq!((&s.a).__specr_get_field(), Int::from(1));
+    assert_eq!((&s.b).__specr_get_field(), Int::from(2));
+
+    (&mut s.a).__specr_set_field(Int::from(3));
+    (&mut s.b).__specr_mutate_field(|b| *b += Int::from(4));
+    (&mut s.a).__specr_mutate_field(|a| *a += Int::from(5));
+    assert_eq!(s.a.extract(), Int::from(8));
+    assert_eq!(s.b, Int::from(6));
+}
@@ -21,6 +21,10 @@ pub use int::*;
 
 mod option;
 
+mod native_index;
+
+mod field;
+
 mod ndresult;
 pub use ndresult::*;
 
@@ -94,6 +98,8 @@ pub mod prelude {
     pub use crate::string::String;
     pub use crate::nondet::{pick, predict};
     pub use crate::option::*;
+    pub use crate::native_index::*;
+    pub use crate::field::*;
 }
 
 // This exists so that `gccompat-derive` can use `libspecr::hidden::GcCompat` to address GcCompat,
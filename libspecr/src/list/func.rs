@@ -38,6 +38,8 @@ impl<T: Obj> List<T> {
     ///
     /// Instead of actual mutation, a new list is allocated, where only the `i`th element is changed.
     /// Then `self` is changed so that it points to that new list.
+    ///
+    /// specr translates `a[b] += c` and `a[b].x = c` to `mutate_at` calls.
     pub fn mutate_at<O: Obj>(&mut self, i: Int, f: impl FnOnce(&mut T) -> O) -> O {
         let i = i.try_to_usize().expect("List::mutate_at: index out of range of `usize`!");
         self.0.mutate(|v| f(&mut v[i]))
@@ -55,6 +57,7 @@ impl<T: Obj> List<T> {
     }
 
     /// Sets the `i`th element of the list.
+    /// specr translates `a[b] = c` to `a.set(b, c)`.
     pub fn set(&mut self, i: Int, t: T) {
         self.mutate_at(i, |r| { *r = t; } )
     }
@@ -155,6 +158,58 @@ impl<T: Obj> List<T> {
         });
     }
 
+    // Returns the start and exclusive end of `range`, panicking if it is out of range.
+    fn range_to_usize(&self, range: impl ListRange, f: &str) -> (usize, usize) {
+        let start = match range.start_bound() {
+            Bound::Included(i) => *i,
+            Bound::Excluded(i) => *i + Int::ONE,
+            Bound::Unbounded => Int::ZERO,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(i) => *i + Int::ONE,
+            Bound::Excluded(i) => *i,
+            Bound::Unbounded => self.len(),
+        };
+
+        if start < Int::ZERO || start > end || end > self.len() {
+            panic!("`List::{f}`: range {start}..{end} out of range for a list of length {}!", self.len());
+        }
+
+        (start.try_to_usize().unwrap(), end.try_to_usize().unwrap())
+    }
+
+    /// The range indexing operator:
+    /// specr translates `a[b..c]` to `a.subslice(b..c)`, and similarly for the other kinds of ranges.
+    pub fn subslice(&self, range: impl ListRange) -> List<T> {
+        let (start, end) = self.range_to_usize(range, "subslice");
+        let v: IMVector<T> = self.0.call_ref_unchecked(|v| v.clone().slice(start..end));
+
+        List(GcCow::new(v))
+    }
+
+    /// Conceptually equivalent to `self[range] = src;`, which specr translates to this.
+    /// Panics if the length of `range` differs from the length of `src`.
+    pub fn write_subslice(&mut self, range: impl ListRange, src: List<T>) {
+        let (start, end) = self.range_to_usize(range, "write_subslice");
+        if Int::from(end - start) != src.len() {
+            panic!("`List::write_subslice`: range of length {} does not match the length {} of `src`!", end - start, src.len());
+        }
+
+        self.write_subslice_at_index(Int::from(start), src);
+    }
+
+    /// Like `mutate_at`, but for the subslice `self[range]`.
+    /// Panics if `f` changes the length of the subslice.
+    /// specr translates assignments like `a[b..c][d] = e` to `mutate_subslice` calls.
+    pub fn mutate_subslice<O: Obj>(&mut self, range: impl ListRange, f: impl FnOnce(&mut List<T>) -> O) -> O {
+        let (start, end) = self.range_to_usize(range, "mutate_subslice");
+        let mut sub = self.subslice(Int::from(start)..Int::from(end));
+        let o = f(&mut sub);
+        self.write_subslice(Int::from(start)..Int::from(end), sub);
+
+        o
+    }
+
     /// Sorts the list with a key extraction function.
     // note that `f` could modify the GC_STATE.
     pub fn sort_by_key<K: Obj + Ord>(&mut self, mut f: impl FnMut(T) -> K) {
@@ -235,4 +290,18 @@ fn test_list() {
     assert_eq!(l.len(), Int::from(2));
     l.push(3);
     assert_eq!(l.len(), Int::from(3));
+    l.set(Int::from(0), 4);
+    l.mutate_at(Int::from(1), |x| *x += 3);
+    assert_eq!(l, list![4, 5, 3]);
+
+    let one = Int::ONE;
+    assert_eq!(l.subslice(one..), list![5, 3]);
+    assert_eq!(l.subslice(..=one), list![4, 5]);
+    l.write_subslice(..Int::from(2), list![1, 2]);
+    l.mutate_subslice(one.., |s| s.set(one, 7));
+    assert_eq!(l, list![1, 2, 7]);
+
+    // the endpoints are inferred to be `Int`s, which is how specr converts literal endpoints.
+    assert_eq!(l.subslice((1usize).into()..), list![2, 7]);
+    assert_eq!(l.subslice(..), l);
 }
@@ -27,6 +27,19 @@ impl<T: Obj> GcCompat for IMVector<T> {
     }
 }
 
+/// The ranges of `Int`s a `List` can be indexed by, like `a..b`, `a..`, `..b`, `a..=b`, `..=b` and `..`.
+///
+/// This is implemented for the range types over `Int` only, and not for all `RangeBounds<Int>`,
+/// so that the endpoints of ranges like `(1usize).into()..`, which specr generates, can be inferred to be `Int`s.
+pub trait ListRange: std::ops::RangeBounds<Int> {}
+
+impl ListRange for std::ops::Range<Int> {}
+impl ListRange for std::ops::RangeFrom<Int> {}
+impl ListRange for std::ops::RangeTo<Int> {}
+impl ListRange for std::ops::RangeInclusive<Int> {}
+impl ListRange for std::ops::RangeToInclusive<Int> {}
+impl ListRange for std::ops::RangeFull {}
+
 // This is not #[derive]d, as this would wrongly require T: Default.
 impl<T: Obj> Default for List<T> {
     fn default() -> Self {
@@ -42,6 +42,21 @@ impl<K: Obj, V: Obj> Map<K, V> {
         })
     }
 
+    /// Like `insert`, but discards the previous value.
+    /// specr translates `a[b] = c` to `a.set(b, c)`.
+    pub fn set(&mut self, k: K, v: V) {
+        self.insert(k, v);
+    }
+
+    /// Conceptually equivalent to `f(&mut self[k])`.
+    /// Mutates the value to the key `k` by giving a mutable ref of it into the function `f`.
+    /// The return value of `f` will be returned from `mutate_at`.
+    ///
+    /// specr translates `a[b] += c` and `a[b].x = c` to `mutate_at` calls.
+    pub fn mutate_at<O: Obj>(&mut self, k: K, f: impl FnOnce(&mut V) -> O) -> O {
+        self.0.mutate(|m| f(m.get_mut(&k).expect("Map::mutate_at: key not in the map!")))
+    }
+
     /// Like `insert`, but fails if `k` was already in the map.
     pub fn try_insert(&mut self, k: K, v: V) -> Result<(), ()> {
         if self.contains_key(k) {
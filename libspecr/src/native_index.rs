@@ -0,0 +1,61 @@
+use std::slice::SliceIndex;
+
+/// Extension trait to implement the methods that specr translates indexing to on slices,
+/// and hence also on arrays and `Vec`s.
+/// This way, `a[b]` keeps working for them in spec code, like it does for `List`.
+///
+/// Types which are not covered, like `HashMap`, need `#[specr::native_index]` on the surrounding item, `let` statement or block.
+pub trait NativeIndexExt<T> {
+    /// specr translates `a[b]` to `a.index_at(b)`.
+    fn index_at(&self, i: usize) -> T;
+
+    /// specr translates `a[b] = c` to `a.set(b, c)`.
+    fn set(&mut self, i: usize, t: T);
+
+    /// Calls `f` on `self[i]`.
+    /// specr translates `a[b] += c` and `a[b].x = c` to `mutate_at` calls.
+    fn mutate_at<O>(&mut self, i: usize, f: impl FnOnce(&mut T) -> O) -> O;
+
+    /// specr translates `a[b..c]` to `a.subslice(b..c)`.
+    fn subslice(&self, range: impl SliceIndex<[T], Output=[T]>) -> &[T];
+
+    /// Calls `f` on `self[range]`.
+    /// specr translates assignments like `a[b..c][d] = e` to `mutate_subslice` calls.
+    fn mutate_subslice<O>(&mut self, range: impl SliceIndex<[T], Output=[T]>, f: impl FnOnce(&mut [T]) -> O) -> O;
+}
+
+impl<T: Clone> NativeIndexExt<T> for [T] {
+    fn index_at(&self, i: usize) -> T {
+        self[i].clone()
+    }
+
+    fn set(&mut self, i: usize, t: T) {
+        self[i] = t;
+    }
+
+    fn mutate_at<O>(&mut self, i: usize, f: impl FnOnce(&mut T) -> O) -> O {
+        f(&mut self[i])
+    }
+
+    fn subslice(&self, range: impl SliceIndex<[T], Output=[T]>) -> &[T] {
+        &self[range]
+    }
+
+    fn mutate_subslice<O>(&mut self, range: impl SliceIndex<[T], Output=[T]>, f: impl FnOnce(&mut [T]) -> O) -> O {
+        f(&mut self[range])
+    }
+}
+
+#[test]
+fn native_index_ext_test() {
+    let mut v = vec![1, 2, 3];
+    v.set(0, 4);
+    v.mutate_at(1, |x| *x += 3);
+    v.mutate_subslice(1.., |s| s.set(1, 7));
+    v.push(8);
+    assert_eq!(v.index_at(0), 4);
+    assert_eq!(v.subslice(1..), &[5, 7, 8]);
+
+    let a = [1, 2];
+    assert_eq!(a.index_at(1), 2);
+}
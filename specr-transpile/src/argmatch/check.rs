@@ -0,0 +1,332 @@
+use crate::argmatch::*;
+use std::collections::HashMap;
+
+/// Reports the problems of the cases `submatches` of `argmatch` which rustc would report far away from the .md files, or not at all:
+/// a declaration without cases, malformed guards, cases which can't be reached due to an earlier one,
+/// and variants no case matches, if we match on an enum of the specr modules.
+pub(super) fn cases(argmatch: &Argmatch, mods: &[Module], submatches: &[FnIdx], resolver: &Resolver, diags: &mut Diagnostics) {
+    let decl = argmatch.method_idx.as_ref(mods);
+    if submatches.is_empty() {
+        let err = Diagnostic::error(format!("`{}` has an `argmatch` declaration, but no cases", decl.sig.ident))
+            .at(decl.sig.ident.span())
+            .note(format!("add cases like `fn {}(..)` with patterns for the matched parameters", decl.sig.ident));
+        diags.emit(err);
+        return;
+    }
+
+    let scope = scope(argmatch, mods, resolver);
+    let cases: Vec<(FnRef, Vec<&Pat>, bool)> = submatches.iter().map(|x| {
+        let iim = x.as_ref(mods);
+        let guarded = match guard(iim) {
+            Ok(guard) => guard.is_some(),
+            // the broken guard is left out of the generated match.
+            Err(err) => {
+                diags.emit(err);
+                false
+            },
+        };
+        (iim, case_pats(argmatch, iim), guarded)
+    }).collect();
+
+    for (j, (iim, pats, _)) in cases.iter().enumerate() {
+        // a case with a guard might not match, so the later cases can still be reached.
+        let Some((earlier, _, _)) = cases[..j].iter().find(|(_, earlier, guarded)| {
+            !guarded && earlier.iter().zip(pats).all(|(e, p)| irrefutable(e, &scope, resolver) || e == p)
+        }) else { continue };
+        let warn = Diagnostic::warning(format!("unreachable case of `{}`", iim.sig.ident))
+            .at(iim.sig.ident.span())
+            .note(format!("the case at {} already matches everything this case does", location(*earlier)))
+            .note("the cases are matched in the order of the modules, and the order they are written down in each module");
+        diags.emit(warn);
+    }
+
+    for (i, m) in argmatch.attr_info.matched.iter().enumerate() {
+        let Some(e) = enum_of(decl, m, &scope, resolver) else { continue };
+
+        // a variant is certainly missing, if no case mentions it.
+        let mut covered = HashSet::new();
+        let mut known = true;
+        for (_, pats, _) in cases.iter().filter(|(_, _, guarded)| !guarded) {
+            match covers(pats[i], &e, &scope, resolver) {
+                Covers::Variants(v) => covered.extend(v),
+                Covers::All | Covers::Unknown => {
+                    known = false;
+                    break;
+                },
+            }
+        }
+        if !known { continue; }
+        let missing: Vec<String> = resolver.variants(&e).iter()
+            .filter(|v| !covered.contains(*v))
+            .map(|v| format!("`{}::{v}`", e.last().unwrap()))
+            .collect();
+        if missing.is_empty() { continue; }
+
+        let err = Diagnostic::error(format!("the cases of `{}` don't cover all values of `{}`", decl.sig.ident, m.ident))
+            .at(decl.sig.ident.span())
+            // rustc doesn't look into guards, so they don't count either.
+            .note(if cases.iter().any(|(_, _, guarded)| *guarded) {
+                format!("no case without a guard matches {}", missing.join(", "))
+            } else {
+                format!("no case matches {}", missing.join(", "))
+            })
+            .note("add cases for them, or a fallback case with a `_` pattern");
+        diags.emit(err);
+    }
+}
+
+/// Reports functions which are defined more than once without an `argmatch` declaration, unless they have different `cfg` attributes.
+/// These were probably meant to be cases of a declaration, which is missing.
+/// `broken` holds the functions whose declaration was reported already.
+pub(super) fn orphans(mods: &[Module], resolver: &Resolver, broken: &[(Container, Ident)], diags: &mut Diagnostics) {
+    let mut by_name: HashMap<String, Vec<FnIdx>> = HashMap::new();
+    let mut names = Vec::new();
+    for f in fns(mods) {
+        let name = f.as_ref(mods).sig.ident.to_string();
+        if !by_name.contains_key(&name) {
+            names.push(name.clone());
+        }
+        by_name.entry(name).or_default().push(f);
+    }
+
+    // the names are visited in the order they are first defined, so the errors are deterministic.
+    for name in names {
+        let fns = &by_name[&name];
+        if fns.len() < 2 { continue; }
+
+        let containers: Vec<Container> = fns.iter().map(|f| f.container(mods, resolver)).collect();
+        for (j, f) in fns.iter().enumerate() {
+            let iim = f.as_ref(mods);
+            if broken.iter().any(|(c, ident)| *c == containers[j] && *ident == iim.sig.ident) { continue; }
+            // definitions under different `#[cfg(..)]`s are alternatives, which is fine.
+            let Some(first) = (0..j).find(|i| containers[*i] == containers[j] && cfgs(fns[*i].as_ref(mods)) == cfgs(iim)) else { continue };
+
+            let err = Diagnostic::error(format!("`{name}` is defined more than once, but has no `argmatch` declaration"))
+                .at(iim.sig.ident.span())
+                .note(format!("it is first defined at {}", location(fns[first].as_ref(mods))))
+                .note(format!("to make these the cases of `{name}`, declare it with `#[specr::argmatch(..)]`"));
+            diags.emit(err);
+        }
+    }
+}
+
+/// Reports `#[specr::guard]` attributes which are not on the cases of an `argmatch`, and hence were not used.
+pub(super) fn stray_guards(mods: &[Module], diags: &mut Diagnostics) {
+    for f in fns(mods) {
+        let iim = f.as_ref(mods);
+        for attr in iim.attrs.iter().filter(|a| is_guard_attr(a)) {
+            let err = Diagnostic::error(format!("`#[specr::guard]` on `{}`, which is not a case of an `argmatch`", iim.sig.ident))
+                .at(attr.pound_token.span)
+                .note("guards can only be added to the cases of a function with an `argmatch` declaration");
+            diags.emit(err);
+        }
+    }
+}
+
+// describes where the `fn` `iim` is, like "lang/values.md:10:8", see `declared_at`.
+fn location(iim: FnRef) -> String {
+    match crate::source_map::locate(iim.sig.ident.span()) {
+        Some(loc) => loc.to_string(),
+        None => format!("`{}`", iim.sig.to_token_stream()),
+    }
+}
+
+// the `#[cfg(..)]` attributes of `iim`, as strings.
+fn cfgs(iim: FnRef) -> Vec<String> {
+    iim.attrs.iter()
+        .filter(|a| a.path().is_ident("cfg"))
+        .map(|a| a.to_token_stream().to_string())
+        .collect()
+}
+
+// the scope of the declaration of `argmatch`, in which the patterns of all cases are resolved.
+pub(super) fn scope(argmatch: &Argmatch, mods: &[Module], resolver: &Resolver) -> Scope {
+    let idx = &argmatch.method_idx;
+    let mut scope = Scope { module: mods[idx.mod_idx].full_path(), ..Scope::default() };
+    if let Item::Impl(ii) = &mods[idx.mod_idx].ast.items[idx.item_idx] {
+        if let Type::Path(tp) = &*ii.self_ty {
+            if let Resolution::Found(res) = resolver.resolve(&scope, &tp.path) {
+                scope.self_ty = Some(res);
+            }
+        }
+    }
+
+    scope
+}
+
+// the path of the enum of the specr modules which the parameter `m` of `decl` has, if it has one.
+fn enum_of(decl: FnRef, m: &MatchArg, scope: &Scope, resolver: &Resolver) -> Option<Vec<String>> {
+    let mut ty = match &decl.sig.inputs[m.idx] {
+        FnArg::Receiver(_) => return match &scope.self_ty {
+            Some(Res::Enum(e)) => Some(e.clone()),
+            _ => None,
+        },
+        FnArg::Typed(pt) => &*pt.ty,
+    };
+    // patterns match through references.
+    while let Type::Reference(TypeReference { elem, .. }) | Type::Paren(TypeParen { elem, .. }) = ty {
+        ty = elem;
+    }
+
+    let Type::Path(tp) = ty else { return None };
+    match resolver.resolve(scope, &tp.path) {
+        Resolution::Found(Res::Enum(e)) => Some(e),
+        _ => None,
+    }
+}
+
+// whether `pat` obviously matches every value, like `_`, `x` or `(_, ref y)`.
+// Idents which are not lowercase are taken to be variants or constants, as they would be in Rust code following the naming conventions.
+fn irrefutable(pat: &Pat, scope: &Scope, resolver: &Resolver) -> bool {
+    match pat {
+        Pat::Wild(_) | Pat::Rest(_) => true,
+        Pat::Ident(pi) => match &pi.subpat {
+            Some((_, sub)) => irrefutable(sub, scope, resolver),
+            // `None` is a variant, not a binding, even if we can't resolve it.
+            None => {
+                let lowercase = pi.ident.to_string().starts_with(|c: char| c.is_lowercase() || c == '_');
+                lowercase && !matches!(resolver.resolve(scope, &pi.ident.clone().into()), Resolution::Found(Res::Variant(..) | Res::Other(_)))
+            },
+        },
+        Pat::Tuple(t) => t.elems.iter().all(|p| irrefutable(p, scope, resolver)),
+        Pat::TupleStruct(ts) => is_struct(&ts.path, scope, resolver) && ts.elems.iter().all(|p| irrefutable(p, scope, resolver)),
+        Pat::Struct(s) => is_struct(&s.path, scope, resolver) && s.fields.iter().all(|f| irrefutable(&f.pat, scope, resolver)),
+        Pat::Or(o) => o.cases.iter().any(|p| irrefutable(p, scope, resolver)),
+        Pat::Paren(p) => irrefutable(&p.pat, scope, resolver),
+        Pat::Reference(r) => irrefutable(&r.pat, scope, resolver),
+        Pat::Type(t) => irrefutable(&t.pat, scope, resolver),
+        _ => false,
+    }
+}
+
+fn is_struct(path: &Path, scope: &Scope, resolver: &Resolver) -> bool {
+    matches!(resolver.resolve(scope, path), Resolution::Found(Res::Struct(_)))
+}
+
+// which variants of an enum a pattern matches.
+enum Covers {
+    All,
+    Variants(Vec<String>),
+    // the pattern contains something we don't understand, like a constant.
+    Unknown,
+}
+
+// returns the variants of the enum `e` which `pat` matches, at least partially.
+fn covers(pat: &Pat, e: &[String], scope: &Scope, resolver: &Resolver) -> Covers {
+    if irrefutable(pat, scope, resolver) {
+        return Covers::All;
+    }
+
+    let path = match pat {
+        Pat::Ident(PatIdent { subpat: Some((_, sub)), .. }) => return covers(sub, e, scope, resolver),
+        Pat::Paren(p) => return covers(&p.pat, e, scope, resolver),
+        Pat::Reference(r) => return covers(&r.pat, e, scope, resolver),
+        Pat::Type(t) => return covers(&t.pat, e, scope, resolver),
+        Pat::Or(o) => {
+            let mut variants = Vec::new();
+            for p in &o.cases {
+                match covers(p, e, scope, resolver) {
+                    Covers::Variants(v) => variants.extend(v),
+                    c => return c,
+                }
+            }
+            return Covers::Variants(variants);
+        },
+        Pat::Ident(pi) => pi.ident.clone().into(),
+        Pat::Path(p) if p.qself.is_none() => p.path.clone(),
+        Pat::TupleStruct(ts) if ts.qself.is_none() => ts.path.clone(),
+        Pat::Struct(s) if s.qself.is_none() => s.path.clone(),
+        _ => return Covers::Unknown,
+    };
+
+    match resolver.resolve(scope, &path) {
+        Resolution::Found(Res::Variant(e2, v)) if e2 == e => Covers::Variants(vec![v]),
+        _ => Covers::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile_rust;
+
+    // the messages for `cases` of `E::f`, which matches on `self`.
+    fn check(cases: &str) -> Vec<String> {
+        let code = format!("
+            pub enum E {{ A, B(u8), C {{ x: u8 }} }}
+            impl E {{
+                #[specr::argmatch(self)]
+                pub fn f(self) -> u8;
+                {cases}
+            }}
+        ");
+        messages(&code)
+    }
+
+    fn messages(code: &str) -> Vec<String> {
+        transpile_rust(&[("lang/a.md", code)]).messages().into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn no_cases() {
+        assert_eq!(check(""), ["`f` has an `argmatch` declaration, but no cases"]);
+        assert_eq!(check("pub fn f(_: Self) -> u8 { 0 }"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unreachable() {
+        let unreachable = ["unreachable case of `f`"];
+        assert_eq!(check("pub fn f(_: Self) -> u8 { 0 } pub fn f(E::A: Self) -> u8 { 1 }"), unreachable);
+        assert_eq!(check("pub fn f(x: Self) -> u8 { 0 } pub fn f(_: Self) -> u8 { 1 }"), unreachable);
+        assert_eq!(check("pub fn f(E::A: Self) -> u8 { 0 } pub fn f(E::A: Self) -> u8 { 1 } pub fn f(_: Self) -> u8 { 2 }"), unreachable);
+
+        // a guard might not match, and `E::B(_)` only matches some values.
+        assert_eq!(check("#[specr::guard(true)] pub fn f(_: Self) -> u8 { 0 } pub fn f(_: Self) -> u8 { 1 }"), Vec::<String>::new());
+        assert_eq!(check("pub fn f(E::B(_): Self) -> u8 { 0 } pub fn f(_: Self) -> u8 { 1 }"), Vec::<String>::new());
+        // `A` is a variant, not a binding.
+        assert_eq!(check("pub fn f(Self::A: Self) -> u8 { 0 } pub fn f(_: Self) -> u8 { 1 }"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn exhaustiveness() {
+        let missing = ["the cases of `f` don't cover all values of `self`"];
+        assert_eq!(check("pub fn f(E::A: Self) -> u8 { 0 } pub fn f(E::B(_): Self) -> u8 { 1 }"), missing);
+        // guards don't count.
+        assert_eq!(check("pub fn f((E::A | E::B(_)): Self) -> u8 { 0 } #[specr::guard(x > 0)] pub fn f(E::C { x }: Self) -> u8 { 1 }"), missing);
+
+        assert_eq!(check("pub fn f((E::A | E::B(_)): Self) -> u8 { 0 } pub fn f(E::C { .. }: Self) -> u8 { 1 }"), Vec::<String>::new());
+        assert_eq!(check("pub fn f(E::A: Self) -> u8 { 0 } pub fn f(other: Self) -> u8 { 1 }"), Vec::<String>::new());
+        // matching on types of other crates is left to rustc.
+        let code = "#[specr::argmatch(x)] pub fn g(x: Option<u8>) -> u8; pub fn g(None: Option<u8>) -> u8 { 0 }";
+        assert_eq!(messages(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn orphans() {
+        let orphan = ["`g` is defined more than once, but has no `argmatch` declaration"];
+        assert_eq!(messages("pub fn g(0: u8) -> u8 { 0 } pub fn g(_: u8) -> u8 { 1 }"), orphan);
+        assert_eq!(messages("pub struct S; impl S { fn g(&self) {} } impl S { fn g(&self) {} }"), orphan);
+
+        // functions of different impl blocks, modules or `cfg`s are no duplicates.
+        assert_eq!(messages("pub struct S; pub struct T; impl S { fn g(&self) {} } impl T { fn g(&self) {} }"), Vec::<String>::new());
+        let t = transpile_rust(&[("lang/a.md", "pub fn g() {}"), ("ext/b.md", "pub fn g() {}")]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        assert_eq!(messages("#[cfg(unix)] pub fn g() {} #[cfg(not(unix))] pub fn g() {}"), Vec::<String>::new());
+        assert_eq!(messages("#[cfg(unix)] pub fn g() {} #[cfg(unix)] pub fn g() {}"), orphan);
+    }
+
+    #[test]
+    fn guards() {
+        assert_eq!(check("#[specr::guard(x > 0)] #[specr::guard(x < 9)] pub fn f(E::B(x): Self) -> u8 { x } pub fn f(_: Self) -> u8 { 0 }"),
+            ["this case of `f` has more than one guard"]);
+        assert_eq!(check("#[specr::guard] pub fn f(E::B(x): Self) -> u8 { x } pub fn f(_: Self) -> u8 { 0 }"),
+            ["expected a condition, like `#[specr::guard(x > 0)]`"]);
+        assert_eq!(check("#[specr::guard(x > 0)] pub fn f(E::B(x): Self) -> u8 { x } pub fn f(_: Self) -> u8 { 0 }"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn stray_guards() {
+        let stray = ["`#[specr::guard]` on `g`, which is not a case of an `argmatch`"];
+        assert_eq!(messages("#[specr::guard(x > 0)] pub fn g(x: u8) -> u8 { x }"), stray);
+        assert_eq!(messages("pub struct S; impl S { #[specr::guard(true)] fn g(&self) {} }"), stray);
+    }
+}
@@ -0,0 +1,924 @@
+use crate::prelude::*;
+use crate::resolve::{Res, Resolution, Resolver, Scope};
+
+mod check;
+
+/// Resolve `argmatches` from the source code, by converting them to a match.
+///
+/// How does `argmatch` work:
+/// There is a method with an argmatch attribute
+/// impl Option<()> {
+///     #[specr::argmatch(self)]
+///     fn foo(self);
+/// }
+///
+/// And then a sequence of submatches:
+/// impl Foo {
+///     fn foo(None: Self) { ... }
+/// }
+///
+/// impl Foo {
+///     fn foo(Some(()): Self) { ... }
+/// }
+///
+/// This will be glued together into:
+///
+/// impl Option<()> {
+///     fn foo(self) {
+///         match self {
+///             None => ...,
+///             Some(()) => ...,
+///         }
+///     }
+/// }
+///
+/// Free functions work the same, and so do trait methods:
+/// there, the submatches are within the same trait, and the declaration gets the match block as its default implementation.
+///
+/// The submatches of methods in impl blocks can be in any module, as long as the impl blocks are for the same type.
+/// Their arms are ordered like the modules, see `source::fetch`.
+/// The bodies of submatches from other modules stay there, as helpers which their arms call, see `helper_names`.
+///
+/// A submatch with a `#[specr::guard(cond)]` attribute gets `cond` as the guard of its arm.
+///
+/// See the README for more information.
+pub fn argmatch(mut mods: Vec<Module>, diags: &mut Diagnostics) -> Vec<Module> {
+    // argmatch only removes functions, so the types and traits of the impl blocks keep resolving the same.
+    let resolver = Resolver::new(&mods);
+    // the functions whose `argmatch` declaration was broken, by their container and name.
+    let mut broken = Vec::new();
+    // the number of helpers generated so far, which keeps their names unique.
+    let mut helper_count = 0;
+
+    while let Some((method_idx, attr_idx)) = locate_argmatch(&mods) {
+        let attr_info = match get_attr_info(method_idx.as_ref(&mods), attr_idx) {
+            Ok(attr_info) => attr_info,
+            Err(err) => {
+                diags.emit(err);
+                // drop the broken attribute, so that we don't find it again.
+                method_idx.attrs_mut(&mut mods).remove(attr_idx);
+                // its cases are still there, but shouldn't be reported once more.
+                broken.push((method_idx.container(&mods, &resolver), method_idx.as_ref(&mods).sig.ident.clone()));
+                continue;
+            },
+        };
+        let argmatch = Argmatch { method_idx, attr_info };
+        let (submatches, rejected) = locate_submatches(&argmatch, &mods, &resolver, diags);
+        check::cases(&argmatch, &mods, &submatches, &resolver, diags);
+        let helpers = helper_names(&argmatch, &mods, &submatches, &mut helper_count, diags);
+        let block = construct_block(&argmatch, &mods, &submatches[..], &helpers[..]);
+
+        // remove the `argmatch` attribute.
+        argmatch.method_idx.attrs_mut(&mut mods).remove(argmatch.attr_info.attr_idx);
+
+        // set the newly-constructed block.
+        argmatch.method_idx.set_block(&mut mods, block);
+
+        // the cases from other modules become helpers, the remaining ones are removed.
+        let submatches = add_helpers(&argmatch, &mut mods, submatches, &helpers, &resolver);
+
+        // the rejected submatches are removed as well, so that they don't cause follow-up errors.
+        clear_submatches(&mut mods, submatches.into_iter().chain(rejected).collect());
+    }
+
+    check::orphans(&mods, &resolver, &broken, diags);
+    check::stray_guards(&mods, diags);
+
+    mods
+}
+
+// represents a free `fn` item, or a `fn` item within an impl or trait block.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct FnIdx {
+    mod_idx: usize,
+    item_idx: usize,
+    // the index within the impl or trait block, `None` for free functions.
+    fn_idx: Option<usize>,
+}
+
+// the parts of a `fn` item which argmatch looks at.
+#[derive(Clone, Copy)]
+struct FnRef<'a> {
+    attrs: &'a [Attribute],
+    sig: &'a Signature,
+    // `None` for trait methods without a default implementation.
+    block: Option<&'a Block>,
+}
+
+impl FnIdx {
+    fn as_ref<'a>(&self, mods: &'a [Module]) -> FnRef<'a> {
+        match (&mods[self.mod_idx].ast.items[self.item_idx], self.fn_idx) {
+            (Item::Fn(f), None) => FnRef { attrs: &f.attrs, sig: &f.sig, block: Some(&f.block) },
+            (Item::Impl(ii), Some(j)) => {
+                let ImplItem::Fn(ref iim) = ii.items[j] else { panic!() };
+                FnRef { attrs: &iim.attrs, sig: &iim.sig, block: Some(&iim.block) }
+            },
+            (Item::Trait(it), Some(j)) => {
+                let TraitItem::Fn(ref tim) = it.items[j] else { panic!() };
+                FnRef { attrs: &tim.attrs, sig: &tim.sig, block: tim.default.as_ref() }
+            },
+            _ => panic!(),
+        }
+    }
+
+    fn attrs_mut<'a>(&self, mods: &'a mut [Module]) -> &'a mut Vec<Attribute> {
+        match (&mut mods[self.mod_idx].ast.items[self.item_idx], self.fn_idx) {
+            (Item::Fn(f), None) => &mut f.attrs,
+            (Item::Impl(ii), Some(j)) => {
+                let ImplItem::Fn(ref mut iim) = ii.items[j] else { panic!() };
+                &mut iim.attrs
+            },
+            (Item::Trait(it), Some(j)) => {
+                let TraitItem::Fn(ref mut tim) = it.items[j] else { panic!() };
+                &mut tim.attrs
+            },
+            _ => panic!(),
+        }
+    }
+
+    fn set_block(&self, mods: &mut [Module], block: Block) {
+        match (&mut mods[self.mod_idx].ast.items[self.item_idx], self.fn_idx) {
+            (Item::Fn(f), None) => *f.block = block,
+            (Item::Impl(ii), Some(j)) => {
+                let ImplItem::Fn(ref mut iim) = ii.items[j] else { panic!() };
+                iim.block = block;
+            },
+            (Item::Trait(it), Some(j)) => {
+                let TraitItem::Fn(ref mut tim) = it.items[j] else { panic!() };
+                tim.default = Some(block);
+                tim.semi_token = None;
+            },
+            _ => panic!(),
+        }
+    }
+
+    fn container(&self, mods: &[Module], resolver: &Resolver) -> Container {
+        let m = &mods[self.mod_idx];
+        match &m.ast.items[self.item_idx] {
+            Item::Fn(_) => Container::Free(m.full_path()),
+            Item::Impl(ii) => {
+                let mut ii = ii.clone();
+                ii.items.clear();
+                // attributes like the documentation don't matter, just like in `merge_impls`.
+                ii.attrs.clear();
+
+                // the type and trait may be named differently in each module.
+                let scope = Scope { module: m.full_path(), ..Scope::default() };
+                if let Type::Path(tp) = &mut *ii.self_ty {
+                    if tp.qself.is_none() {
+                        canonicalize(&mut tp.path, &scope, resolver);
+                    }
+                }
+                if let Some((_, path, _)) = &mut ii.trait_ {
+                    canonicalize(path, &scope, resolver);
+                }
+
+                Container::Impl(ii)
+            },
+            Item::Trait(it) => {
+                let mut it = it.clone();
+                it.items.clear();
+                it.attrs.clear();
+
+                Container::Trait(m.full_path(), it)
+            },
+            _ => panic!(),
+        }
+    }
+}
+
+// where a `fn` item is declared.
+// Free functions and traits are items of their module, so their submatches have to be in the same module.
+// Impl blocks for the same type can be in any module.
+#[derive(PartialEq)]
+enum Container {
+    Free(Vec<String>),
+    // the trait without its items.
+    Trait(Vec<String>, ItemTrait),
+    // the impl block without its items.
+    Impl(ItemImpl),
+}
+
+// replaces `path` by the full path of the struct, enum, variant or trait it refers to, like `crate::lang::Value<T>`.
+// Paths to items outside of the specr modules, like `Option<T>`, are kept as they are.
+fn canonicalize(path: &mut Path, scope: &Scope, resolver: &Resolver) {
+    let Resolution::Found(res @ (Res::Enum(_) | Res::Variant(..) | Res::Struct(_) | Res::Other(_))) = resolver.resolve(scope, path) else { return };
+
+    let args = path.segments.last().unwrap().arguments.clone();
+    let idents = res.to_path_string().split("::").map(|x| format_ident!("{x}")).collect::<Vec<_>>();
+    *path = parse_quote! { crate #(::#idents)* };
+    path.segments.last_mut().unwrap().arguments = args;
+}
+
+// canonicalizes the paths of a pattern, so that it means the same in every module.
+struct Canonicalize<'a> {
+    scope: &'a Scope,
+    resolver: &'a Resolver,
+}
+
+impl VisitMut for Canonicalize<'_> {
+    fn visit_pat_mut(&mut self, pat: &mut Pat) {
+        match pat {
+            Pat::Path(ExprPath { qself: None, path, .. }) => canonicalize(path, self.scope, self.resolver),
+            // a name is no binding if it refers to a unit variant or struct, like `Nil`.
+            Pat::Ident(PatIdent { by_ref: None, mutability: None, subpat: None, ident, attrs }) => {
+                let path: Path = ident.clone().into();
+                if let Resolution::Found(Res::Variant(..) | Res::Struct(_)) = self.resolver.resolve(self.scope, &path) {
+                    let mut path = path;
+                    canonicalize(&mut path, self.scope, self.resolver);
+                    *pat = Pat::Path(ExprPath { attrs: attrs.clone(), qself: None, path });
+                }
+            },
+            _ => {},
+        }
+        visit_pat_mut(self, pat);
+    }
+
+    fn visit_pat_tuple_struct_mut(&mut self, pts: &mut PatTupleStruct) {
+        if pts.qself.is_none() {
+            canonicalize(&mut pts.path, self.scope, self.resolver);
+        }
+        visit_pat_tuple_struct_mut(self, pts);
+    }
+
+    fn visit_pat_struct_mut(&mut self, ps: &mut PatStruct) {
+        if ps.qself.is_none() {
+            canonicalize(&mut ps.path, self.scope, self.resolver);
+        }
+        visit_pat_struct_mut(self, ps);
+    }
+}
+
+// returns all free fns, and all fns within impl and trait blocks,
+// in the order of the modules and the order they are written down in the input files.
+fn fns(mods: &[Module]) -> Vec<FnIdx> {
+    let mut fns = Vec::new();
+    for (m, module) in mods.iter().enumerate() {
+        for (i, x) in module.ast.items.iter().enumerate() {
+            match x {
+                Item::Fn(_) => fns.push(FnIdx { mod_idx: m, item_idx: i, fn_idx: None }),
+                Item::Impl(ii) => {
+                    for (j, y) in ii.items.iter().enumerate() {
+                        let ImplItem::Fn(_) = y else { continue };
+                        fns.push(FnIdx { mod_idx: m, item_idx: i, fn_idx: Some(j) });
+                    }
+                },
+                Item::Trait(it) => {
+                    for (j, y) in it.items.iter().enumerate() {
+                        let TraitItem::Fn(_) = y else { continue };
+                        fns.push(FnIdx { mod_idx: m, item_idx: i, fn_idx: Some(j) });
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fns
+}
+
+// expresses everything that can be contained in an `specr::argmatch` attribute.
+struct AttrInfo {
+    // which attribute is the argmatch attribute.
+    attr_idx: usize,
+
+    // the function arguments we match upon, in the order of the attribute.
+    // Typically this is just `self`.
+    matched: Vec<MatchArg>,
+}
+
+// a function argument we match upon.
+struct MatchArg {
+    // the index and ident of the argument.
+    // Typically `idx = 0` and `ident = self`.
+    idx: usize,
+    ident: Ident,
+}
+
+struct Argmatch {
+    // the method which has the `argmatch` attribute.
+    method_idx: FnIdx,
+
+    // the information in this attribute.
+    attr_info: AttrInfo,
+}
+
+// finds a fn with an #[specr::argmatch] attribute, and the index of that attribute.
+fn locate_argmatch(mods: &[Module]) -> Option<(FnIdx, usize)> {
+    fns(mods).into_iter().find_map(|method_idx| {
+        let attr_idx = method_idx.as_ref(mods).attrs.iter().position(is_argmatch_attr)?;
+        Some((method_idx, attr_idx))
+    })
+}
+
+fn construct_block(argmatch: &Argmatch, mods: &[Module], submatches: &[FnIdx], helpers: &[Option<Ident>]) -> Block {
+    let matched = &argmatch.attr_info.matched;
+    let pats: Vec<TokenStream> = submatches.iter().map(|x| {
+            let pats = case_pats(argmatch, x.as_ref(mods));
+
+            // matching on multiple arguments matches on the tuple of them.
+            match &pats[..] {
+                [pat] => pat.to_token_stream(),
+                _ => quote! { (#(#pats),*) },
+            }
+        }).collect();
+    // malformed guards are reported by `check::cases`.
+    let guards: Vec<Option<TokenStream>> = submatches.iter().map(|x| {
+            let guard = guard(x.as_ref(mods)).ok().flatten()?;
+            Some(quote! { if #guard })
+        }).collect();
+    // the bindings of the patterns are only used by the helper, which binds them once more.
+    let attrs: Vec<Option<TokenStream>> = helpers.iter().map(|h| {
+            h.as_ref().map(|_| quote! { #[allow(unused_variables)] })
+        }).collect();
+    let blocks: Vec<Block> = submatches.iter().zip(helpers).map(|(x, helper)| {
+            match helper {
+                Some(name) => {
+                    let args = forwarded(argmatch.method_idx.as_ref(mods).sig);
+                    parse_quote! {{ Self::#name(#(#args),*) }}
+                },
+                // `is_submatch` made sure that every submatch has a body.
+                None => x.as_ref(mods).block.unwrap().clone(),
+            }
+        }).collect();
+
+    let idents = matched.iter().map(|m| &m.ident);
+    let scrutinee = match &matched[..] {
+        [m] => m.ident.to_token_stream(),
+        _ => quote! { (#(#idents),*) },
+    };
+
+    let tokens = quote! {{
+        match #scrutinee {
+            #(#attrs #pats #guards => #blocks,)*
+        }
+    }};
+    parse2(tokens).expect("Cannot parse block!")
+}
+
+// returns the patterns of the submatch `iim` for the arguments we match upon.
+fn case_pats<'a>(argmatch: &Argmatch, iim: FnRef<'a>) -> Vec<&'a Pat> {
+    argmatch.attr_info.matched.iter().map(|m| {
+        // `is_submatch` made sure that this is a pattern.
+        let FnArg::Typed(ref pt) = iim.sig.inputs[m.idx] else {
+            panic!("expected match-able pattern, got `self`!")
+        };
+
+        &*pt.pat
+    }).collect()
+}
+
+// The body of a case from another module would be resolved in the module of the declaration,
+// where the private items and imports of its own module can't be reached.
+// So it stays in its module as a helper, which its arm calls with the arguments of the declaration.
+// Returns the names of the helpers, `None` for the cases within the module of the declaration.
+fn helper_names(argmatch: &Argmatch, mods: &[Module], submatches: &[FnIdx], count: &mut usize, diags: &mut Diagnostics) -> Vec<Option<Ident>> {
+    let decl = argmatch.method_idx.as_ref(mods);
+    let mut moved = submatches.iter().filter(|x| x.mod_idx != argmatch.method_idx.mod_idx);
+    let Some(first) = moved.next() else { return vec![None; submatches.len()] };
+
+    // a parameter which isn't a name can't be passed on, the case body is then kept in the declaration.
+    let unnamed = decl.sig.inputs.iter().find_map(|arg| match arg {
+        FnArg::Typed(pt) if !matches!(&*pt.pat, Pat::Wild(_) | Pat::Ident(PatIdent { by_ref: None, .. })) => Some(pt),
+        _ => None,
+    });
+    if let Some(pt) = unnamed {
+        let case = match crate::source_map::locate(first.as_ref(mods).sig.ident.span()) {
+            Some(loc) => format!("the case at {loc}"),
+            None => "a case".to_string(),
+        };
+        let err = Diagnostic::error(format!("`{}` can't pass this parameter on to its cases from other modules", decl.sig.ident))
+            .at(spanned::Spanned::span(&pt.pat))
+            .note(format!("{case} is in another module"))
+            .note(format!("give the parameter a name, like `x: {}`", pt.ty.to_token_stream()));
+        diags.emit(err);
+        return vec![None; submatches.len()];
+    }
+
+    submatches.iter().map(|x| {
+        if x.mod_idx == argmatch.method_idx.mod_idx { return None; }
+        let name = format_ident!("__specr_{}_case{}", decl.sig.ident, count);
+        *count += 1;
+
+        Some(name)
+    }).collect()
+}
+
+// the arguments of the declaration `sig`, as passed on to the helpers.
+// `_` parameters are left out, as they can't be named.
+fn forwarded(sig: &Signature) -> Vec<TokenStream> {
+    sig.inputs.iter().filter_map(|arg| match arg {
+        FnArg::Receiver(r) => Some(r.self_token.to_token_stream()),
+        FnArg::Typed(pt) => match &*pt.pat {
+            Pat::Ident(pi) => Some(pi.ident.to_token_stream()),
+            // `helper_names` made sure that every other parameter is `_`.
+            _ => None,
+        },
+    }).collect()
+}
+
+// turns the case `case` into the helper `name`, which binds the patterns of the matched parameters once more:
+// `pub(crate) fn __specr_foo_case0(__specr_arg0: Self, x: Int) -> R { let <pattern> = __specr_arg0 else { unreachable!() }; <the body> }`
+// Like in the arm, the patterns are resolved in the module of the declaration, `scope`.
+fn helper(argmatch: &Argmatch, case: FnRef, name: &Ident, scope: &Scope, resolver: &Resolver) -> ImplItemFn {
+    let mut sig = case.sig.clone();
+    sig.ident = name.clone();
+
+    let mut pats = Vec::new();
+    let mut args = Vec::new();
+    for m in &argmatch.attr_info.matched {
+        let arg = format_ident!("__specr_arg{}", m.idx);
+        // `is_submatch` made sure that this is a pattern.
+        let FnArg::Typed(pt) = &mut sig.inputs[m.idx] else { panic!() };
+        let mut pat = std::mem::replace(&mut *pt.pat, parse_quote! { #arg });
+        Canonicalize { scope, resolver }.visit_pat_mut(&mut pat);
+        pats.push(pat);
+        args.push(arg);
+    }
+    sig.inputs = sig.inputs.into_iter()
+        .filter(|arg| !matches!(arg, FnArg::Typed(pt) if matches!(&*pt.pat, Pat::Wild(_))))
+        .collect();
+
+    let (pat, scrutinee) = match (&pats[..], &args[..]) {
+        ([pat], [arg]) => (pat.to_token_stream(), arg.to_token_stream()),
+        _ => (quote! { (#(#pats),*) }, quote! { (#(#args),*) }),
+    };
+    let attrs = case.attrs.iter().filter(|a| !is_guard_attr(a));
+    // `is_submatch` made sure that every submatch has a body.
+    let stmts = &case.block.unwrap().stmts;
+
+    parse_quote! {
+        #(#attrs)*
+        #[allow(irrefutable_let_patterns)]
+        pub(crate) #sig {
+            let #pat = #scrutinee else { unreachable!() };
+            #(#stmts)*
+        }
+    }
+}
+
+// replaces the cases from other modules by their helpers, and returns the other cases.
+// Trait impls can't have further methods, so their helpers get an inherent impl block of their own.
+fn add_helpers(argmatch: &Argmatch, mods: &mut [Module], submatches: Vec<FnIdx>, helpers: &[Option<Ident>], resolver: &Resolver) -> Vec<FnIdx> {
+    let scope = check::scope(argmatch, mods, resolver);
+    let mut rest = Vec::new();
+    for (x, name) in submatches.into_iter().zip(helpers) {
+        let Some(name) = name else {
+            rest.push(x);
+            continue;
+        };
+        let f = helper(argmatch, x.as_ref(mods), name, &scope, resolver);
+        let items = &mut mods[x.mod_idx].ast.items;
+        // only the cases of impl blocks can be in other modules, see `Container`.
+        let Item::Impl(ii) = &mut items[x.item_idx] else { panic!() };
+        if ii.trait_.is_none() {
+            ii.items[x.fn_idx.unwrap()] = ImplItem::Fn(f);
+        } else {
+            let (generics, _, where_clause) = ii.generics.split_for_impl();
+            let self_ty = &ii.self_ty;
+            let item: Item = parse_quote! {
+                impl #generics #self_ty #where_clause {
+                    #f
+                }
+            };
+            // appending the impl block keeps the indices of the other items valid.
+            items.push(item);
+            rest.push(x);
+        }
+    }
+
+    rest
+}
+
+// returns the submatches in the order of the modules and the order they are written down in the input files,
+// and the rejected ones: the mismatching submatches and the duplicate declarations of the same function.
+fn locate_submatches(argmatch: &Argmatch, mods: &[Module], resolver: &Resolver, diags: &mut Diagnostics) -> (Vec<FnIdx>, Vec<FnIdx>) {
+    let mut submatches = Vec::new();
+    let mut rejected = Vec::new();
+
+    for method_idx in fns(mods) {
+        match is_submatch(argmatch, &method_idx, mods, resolver) {
+            SubmatchResult::Yes => {
+                submatches.push(method_idx);
+            },
+            SubmatchResult::No => {},
+            SubmatchResult::YesButMismatch(err) => {
+                diags.emit(err);
+                rejected.push(method_idx);
+            },
+            SubmatchResult::Duplicate(err) => {
+                diags.emit(err);
+                rejected.push(method_idx);
+            },
+        }
+    }
+
+    (submatches, rejected)
+}
+
+enum SubmatchResult {
+    Yes,
+    No,
+
+    // It seems to be a submatch, but something is off.
+    // This generates an error.
+    YesButMismatch(Diagnostic),
+
+    // It is another `argmatch` declaration of the same function.
+    // This generates an error.
+    Duplicate(Diagnostic),
+}
+
+fn is_submatch(argmatch: &Argmatch, method_idx: &FnIdx, mods: &[Module], resolver: &Resolver) -> SubmatchResult {
+    if *method_idx == argmatch.method_idx {
+        // this is no "submatch", it's the original method_idx itself!
+        return SubmatchResult::No;
+    }
+
+    let iim1 = argmatch.method_idx.as_ref(mods);
+    let iim2 = method_idx.as_ref(mods);
+
+    // check that the methods have the same name.
+    if iim1.sig.ident != iim2.sig.ident {
+        return SubmatchResult::No;
+    }
+
+    // check that the impl or trait blocks are compatible (including type, optional trait, generics),
+    // or that both are free functions of the same module.
+    if argmatch.method_idx.container(mods, resolver) != method_idx.container(mods, resolver) {
+        return SubmatchResult::No;
+    }
+
+    if iim2.attrs.iter().any(is_argmatch_attr) {
+        let err = Diagnostic::error(format!("`{}` has more than one `argmatch` declaration", iim2.sig.ident))
+            .at(iim2.sig.ident.span())
+            .note(declared_at(iim1))
+            .note("the other declarations should be cases of the first one");
+        return SubmatchResult::Duplicate(err);
+    }
+
+    // check that signature are the same, except for the FnArgs we match upon.
+    let hide_match_ident = |sig: &Signature| {
+        let mut sig = sig.clone();
+        for m in &argmatch.attr_info.matched {
+            // a submatch with too few arguments is caught by the comparison below.
+            if let Some(arg) = sig.inputs.iter_mut().nth(m.idx) {
+                *arg = parse2(quote!{self}).unwrap();
+            }
+        }
+
+        sig
+    };
+    let sig1 = hide_match_ident(iim1.sig);
+    let sig2 = hide_match_ident(iim2.sig);
+
+    if sig1 != sig2 {
+        let err = Diagnostic::error(format!("signature mismatch between `{}` and its `argmatch` declaration", iim2.sig.ident))
+            .at(iim2.sig.ident.span())
+            .note(format!("expected `{}`", iim1.sig.to_token_stream()))
+            .note(format!("found `{}`", iim2.sig.to_token_stream()))
+            .note(declared_at(iim1));
+        return SubmatchResult::YesButMismatch(err);
+    }
+
+    for m in &argmatch.attr_info.matched {
+        if let FnArg::Receiver(r) = &iim2.sig.inputs[m.idx] {
+            let err = Diagnostic::error(format!("expected a pattern to match `{}` against, found `self`", m.ident))
+                .at(r.self_token.span)
+                .note(declared_at(iim1));
+            return SubmatchResult::YesButMismatch(err);
+        }
+    }
+
+    if iim2.block.is_none() {
+        let err = Diagnostic::error(format!("expected a body for this case of `{}`", iim2.sig.ident))
+            .at(iim2.sig.ident.span())
+            .note(declared_at(iim1));
+        return SubmatchResult::YesButMismatch(err);
+    }
+
+    SubmatchResult::Yes
+
+}
+
+fn clear_submatches(mods: &mut [Module], mut submatches: Vec<FnIdx>) {
+    // removing the later fns first keeps the indices of the others valid.
+    submatches.sort();
+    submatches.reverse();
+
+    for s in submatches {
+        let ast = &mut mods[s.mod_idx].ast;
+        let empty = match (&mut ast.items[s.item_idx], s.fn_idx) {
+            (Item::Fn(_), None) => true,
+            (Item::Impl(ii), Some(j)) => {
+                ii.items.remove(j);
+                ii.items.is_empty()
+            },
+            (Item::Trait(it), Some(j)) => {
+                it.items.remove(j);
+                it.items.is_empty()
+            },
+            _ => panic!(),
+        };
+
+        // it the resulting impl or trait block would then be empty, remove it.
+        if empty {
+            ast.items.remove(s.item_idx);
+        }
+    }
+}
+
+fn declared_at(iim: FnRef) -> String {
+    match crate::source_map::locate(iim.sig.ident.span()) {
+        Some(loc) => format!("the `argmatch` is declared at {loc}"),
+        None => format!("the `argmatch` is declared at `{}`", iim.sig.ident),
+    }
+}
+
+pub(crate) fn is_argmatch_attr(attr: &Attribute) -> bool {
+    is_specr_attr(attr, "argmatch")
+}
+
+fn is_guard_attr(attr: &Attribute) -> bool {
+    is_specr_attr(attr, "guard")
+}
+
+// whether `attr` is `#[specr::<name>]`.
+fn is_specr_attr(attr: &Attribute, name: &str) -> bool {
+    let segments: Vec<String> = attr.path().segments
+                                        .iter()
+                                        .map(|x| format!("{}", x.to_token_stream()))
+                                        .collect();
+
+    matches!(&segments[..], [l, r] if l == "specr" && r == name)
+}
+
+// Returns the condition of the `#[specr::guard(cond)]` attribute of the submatch `iim`, if it has one.
+fn guard(iim: FnRef) -> std::result::Result<Option<Expr>, Diagnostic> {
+    let mut guards = iim.attrs.iter().filter(|a| is_guard_attr(a));
+    let Some(attr) = guards.next() else { return Ok(None) };
+    if let Some(other) = guards.next() {
+        let err = Diagnostic::error(format!("this case of `{}` has more than one guard", iim.sig.ident))
+            .at(other.pound_token.span)
+            .note("combine the conditions with `&&`");
+        return Err(err);
+    }
+
+    attr.parse_args().map(Some).map_err(|_| {
+        Diagnostic::error("expected a condition, like `#[specr::guard(x > 0)]`")
+            .at(attr.pound_token.span)
+    })
+}
+
+// Returns the info of the `argmatch` attribute `attrs[attr_idx]`.
+fn get_attr_info(iim: FnRef, attr_idx: usize) -> std::result::Result<AttrInfo, Diagnostic> {
+    let attr = &iim.attrs[attr_idx];
+    let malformed = || Diagnostic::error("expected the parameters to match on, like `#[specr::argmatch(self)]` or `#[specr::argmatch(a, b)]`")
+        .at(attr.pound_token.span);
+
+    let Meta::List(list) = &attr.meta else { return Err(malformed()) };
+    // `self` is a keyword, so it needs `parse_any`.
+    let parser = |input: parse::ParseStream| Punctuated::<Ident, Token![,]>::parse_terminated_with(input, ext::IdentExt::parse_any);
+    let Ok(idents) = list.parse_args_with(parser) else { return Err(malformed()) };
+    if idents.is_empty() {
+        return Err(malformed());
+    }
+
+    let mut matched: Vec<MatchArg> = Vec::new();
+    for ident in idents {
+        if matched.iter().any(|m| m.ident == ident) {
+            let err = Diagnostic::error(format!("`{ident}` is matched on more than once"))
+                .at(ident.span());
+            return Err(err);
+        }
+        let idx = match_idx(iim.sig, &ident).ok_or_else(|| {
+            Diagnostic::error(format!("`{ident}` is not a parameter of `{}`", iim.sig.ident))
+                .at(ident.span())
+        })?;
+        matched.push(MatchArg { idx, ident });
+    }
+
+    Ok(AttrInfo { attr_idx, matched })
+}
+
+// Returns the index of the parameter `ident` of `sig`, if there is one.
+fn match_idx(sig: &Signature, ident: &Ident) -> Option<usize> {
+    if ident == "self" {
+        match sig.inputs.first() {
+            Some(FnArg::Receiver(_)) => Some(0),
+            _ => None,
+        }
+    } else {
+        sig.inputs.iter().position(|arg| {
+            let FnArg::Typed(pat_ty) = arg else { return false };
+            let Pat::Ident(pi) = &*pat_ty.pat else { return false };
+
+            pi.ident == *ident
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile_rust;
+
+    const DECL: &str = "
+        use crate::prelude::*;
+        pub enum E { A, B(Int) }
+        impl E {
+            #[specr::argmatch(self)]
+            pub fn f(self, k: Int, _: bool) -> Int;
+            pub fn f(E::A: Self, k: Int, _: bool) -> Int { k }
+        }
+    ";
+
+    fn squash(s: &str) -> String {
+        s.split_whitespace().collect()
+    }
+
+    // the case from `ext` calls a private function of `ext`, so it has to stay there.
+    #[test]
+    fn cross_module() {
+        let ext = "
+            use crate::lang::E as F;
+            fn double(x: Int) -> Int { x + x }
+            impl F {
+                pub fn f(E::B(n): Self, k: Int, _: bool) -> Int { double(n) + k }
+            }
+        ";
+        let t = transpile_rust(&[("lang/a.md", DECL), ("ext/b.md", ext)]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        assert!(t.code("src/lang.rs").contains(&squash("
+            #[allow(unused_variables)]
+            E::B(n) => Self::__specr_f_case0(self, k),
+        ")));
+        assert!(t.code("src/ext.rs").contains(&squash("
+            pub(crate) fn __specr_f_case0(__specr_arg0: Self, k: Int) -> Int {
+                let crate::lang::E::B(n) = __specr_arg0 else { unreachable!() };
+                double(n) + k
+            }
+        ")));
+    }
+
+    // the cases within the module of the declaration are kept in the `match`.
+    #[test]
+    fn same_module() {
+        let t = transpile_rust(&[("lang/a.md", &format!("{DECL} impl E {{ pub fn f(E::B(n): Self, k: Int, _: bool) -> Int {{ n }} }}"))]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        assert!(t.code("src/lang.rs").contains(&squash("E::B(n) => n,")));
+        assert!(!t.code("src/lang.rs").contains("__specr_f_case"));
+    }
+
+    #[test]
+    fn unnamed_parameter() {
+        let decl = "
+            use crate::prelude::*;
+            pub enum E { A, B(Int) }
+            impl E {
+                #[specr::argmatch(self)]
+                pub fn f(self, (a, b): (Int, Int)) -> Int;
+            }
+        ";
+        let ext = "impl crate::lang::E { pub fn f(crate::lang::E::B(n): Self, (a, b): (Int, Int)) -> Int { n }
+            pub fn f(_: Self, (a, b): (Int, Int)) -> Int { a } }";
+        let t = transpile_rust(&[("lang/a.md", decl), ("ext/b.md", ext)]);
+        assert_eq!(t.messages(), vec!["`f` can't pass this parameter on to its cases from other modules"]);
+    }
+
+    fn messages(code: &str) -> Vec<String> {
+        transpile_rust(&[("lang/a.md", code)]).messages().into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn multiple_args() {
+        let code = "
+            use crate::prelude::*;
+            pub enum E { A, B }
+            impl E {
+                #[specr::argmatch(self, r)]
+                pub fn f(self, l: bool, r: Option<bool>) -> bool;
+                pub fn f(E::A: Self, l: bool, Some(r): Option<bool>) -> bool { l && r }
+                pub fn f(_: Self, l: bool, _: Option<bool>) -> bool { l }
+            }
+        ";
+        let t = transpile_rust(&[("lang/a.md", code)]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        assert!(t.code("src/lang.rs").contains(&squash("
+            match (self, r) {
+                (E::A, Some(r)) => l && r,
+                (_, _) => l,
+            }
+        ")));
+    }
+
+    #[test]
+    fn multiple_args_errors() {
+        let decl = |attr: &str, case: &str| format!("
+            use crate::prelude::*;
+            pub struct S;
+            impl S {{
+                #[specr::argmatch({attr})]
+                pub fn f(&self, a: bool, b: bool) -> bool;
+                {case}
+            }}
+        ");
+        assert_eq!(messages(&decl("a, a", "")), ["`a` is matched on more than once"]);
+        assert_eq!(messages(&decl("a, c", "")), ["`c` is not a parameter of `f`"]);
+        assert_eq!(messages(&decl("", "")), ["expected the parameters to match on, like `#[specr::argmatch(self)]` or `#[specr::argmatch(a, b)]`"]);
+        // the other parameters have to be the same as in the declaration.
+        assert_eq!(messages(&decl("a, b", "pub fn f(&self, true: bool, b: bool, c: bool) -> bool { b }")),
+            ["signature mismatch between `f` and its `argmatch` declaration", "`f` has an `argmatch` declaration, but no cases"]);
+        assert_eq!(messages(&decl("self, a", "pub fn f(&self, true: bool, b: bool) -> bool { b }")),
+            ["expected a pattern to match `self` against, found `self`", "`f` has an `argmatch` declaration, but no cases"]);
+    }
+
+    #[test]
+    fn free_fns() {
+        let code = "
+            #[specr::argmatch(n)]
+            pub fn g(n: u8, k: u8) -> u8;
+            pub fn g(0: u8, k: u8) -> u8 { k }
+            pub fn g(n: u8, k: u8) -> u8 { n }
+        ";
+        let t = transpile_rust(&[("lang/a.md", code)]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        assert!(t.code("src/lang.rs").contains(&squash("pub fn g(n: u8, k: u8) -> u8 { match n { 0 => k, n => n, } }")));
+
+        // free functions of other modules are no cases.
+        let t = transpile_rust(&[("lang/a.md", "#[specr::argmatch(n)] pub fn g(n: u8) -> u8;"), ("ext/b.md", "pub fn g(0: u8) -> u8 { 1 }")]);
+        assert_eq!(t.messages(), ["`g` has an `argmatch` declaration, but no cases"]);
+    }
+
+    #[test]
+    fn traits() {
+        let code = "
+            pub trait Shape {
+                #[specr::argmatch(n)]
+                fn name(&self, n: u8) -> &'static str;
+                fn name(&self, 3: u8) -> &'static str { \"triangle\" }
+            }
+            pub trait Shape {
+                fn name(&self, _: u8) -> &'static str { \"other\" }
+            }
+        ";
+        let t = transpile_rust(&[("lang/a.md", code)]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        let code = t.code("src/lang.rs");
+        assert!(code.contains(&squash("fn name(&self, n: u8) -> &'static str { match n { 3 => \"triangle\", _ => \"other\", } }")));
+        assert_eq!(code.matches("pubtraitShape").count(), 1);
+
+        // the cases of a trait need a body.
+        let code = "pub trait Shape { #[specr::argmatch(n)] fn name(&self, n: u8) -> u8; fn name(&self, 3: u8) -> u8; }";
+        assert_eq!(messages(code), ["expected a body for this case of `name`", "`name` has an `argmatch` declaration, but no cases"]);
+    }
+
+    #[test]
+    fn trait_impls() {
+        let code = "
+            use crate::prelude::*;
+            pub trait Tr { fn f(self) -> u8; }
+            pub enum E { A, B }
+            pub struct S;
+            impl Tr for E {
+                #[specr::argmatch(self)]
+                fn f(self) -> u8;
+                fn f(E::A: Self) -> u8 { 1 }
+            }
+            impl Tr for E {
+                fn f(E::B: Self) -> u8 { 2 }
+            }
+        ";
+        let t = transpile_rust(&[("lang/a.md", code)]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        assert!(t.code("src/lang.rs").contains(&squash("fn f(self) -> u8 { match self { E::A => 1, E::B => 2, } }")));
+
+        // a case of the impl for another type is no case.
+        let code = "
+            pub trait Tr { fn f(self) -> u8; }
+            pub struct S;
+            pub struct T;
+            impl Tr for S {
+                #[specr::argmatch(self)]
+                fn f(self) -> u8;
+            }
+            impl Tr for T {
+                fn f(T: Self) -> u8 { 2 }
+            }
+        ";
+        assert_eq!(messages(code), ["`f` has an `argmatch` declaration, but no cases"]);
+    }
+
+    #[test]
+    fn guards() {
+        let code = "
+            #[specr::argmatch(n)]
+            pub fn g(n: u8, k: u8) -> u8;
+            #[specr::guard(n > k)]
+            pub fn g(n: u8, k: u8) -> u8 { n }
+            pub fn g(_: u8, k: u8) -> u8 { k }
+        ";
+        let t = transpile_rust(&[("lang/a.md", code)]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        assert!(t.code("src/lang.rs").contains(&squash("match n { n if n > k => n, _ => k, }")));
+    }
+}
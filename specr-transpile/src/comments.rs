@@ -0,0 +1,206 @@
+//! Finds the comments in Rust code.
+//!
+//! `syn` drops all comments except doc comments, so we lex them on our own
+//! and later re-insert them into the generated code, see `source_map::unparse`.
+
+/// A line or block comment, which is not a doc comment.
+pub struct Comment {
+    /// the (1-based) line and (0-based) column, in chars, where the comment starts.
+    pub start: (usize, usize),
+    /// the comment itself, including the `//` or `/* */`.
+    pub text: String,
+    /// whether the comment is the first thing on its line.
+    pub own_line: bool,
+}
+
+/// Returns all comments in `code`, in the order they appear.
+pub fn lex(code: &str) -> Vec<Comment> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut out = Vec::new();
+
+    let mut i = 0;
+    let (mut line, mut col) = (1, 0);
+    // whether only whitespace preceded `i` on its line.
+    let mut line_start = true;
+
+    // moves `i` forward by `n` chars, keeping track of the position.
+    let advance = |i: &mut usize, line: &mut usize, col: &mut usize, n: usize| {
+        for _ in 0..n {
+            if chars.get(*i) == Some(&'\n') {
+                *line += 1;
+                *col = 0;
+            } else {
+                *col += 1;
+            }
+            *i += 1;
+        }
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i+1).copied();
+        let start = (line, col);
+        let own_line = line_start;
+        line_start = c == '\n' || (line_start && c.is_whitespace());
+
+        let len = match (c, next) {
+            ('/', Some('/')) => {
+                let len = chars[i..].iter().take_while(|c| **c != '\n').count();
+                let text: String = chars[i..i+len].iter().collect();
+                // `///` and `//!` are doc comments, but `////` is not.
+                let doc = (text.starts_with("///") && !text.starts_with("////")) || text.starts_with("//!");
+                if !doc {
+                    out.push(Comment { start, text: text.trim_end().to_string(), own_line });
+                }
+                len
+            },
+            ('/', Some('*')) => {
+                let len = block_comment_len(&chars[i..]);
+                let text: String = chars[i..i+len].iter().collect();
+                // `/**` and `/*!` are doc comments, but `/**/` and `/***` are not.
+                let doc = (text.starts_with("/**") && !text.starts_with("/**/") && !text.starts_with("/***")) || text.starts_with("/*!");
+                if !doc {
+                    out.push(Comment { start, text, own_line });
+                }
+                len
+            },
+            ('"', _) => string_len(&chars[i..]),
+            ('r', Some('"' | '#')) if !is_ident_char(&chars, i) => raw_string_len(&chars[i+1..]).map_or(1, |n| n + 1),
+            ('b' | 'c', Some('r')) if !is_ident_char(&chars, i) => raw_string_len(&chars[i+2..]).map_or(1, |n| n + 2),
+            ('\'', _) => char_len(&chars[i..]),
+            _ if c.is_alphanumeric() || c == '_' => {
+                // skip whole identifiers, so that e.g. the `r` of `for` is not taken for a raw string.
+                chars[i..].iter().take_while(|c| c.is_alphanumeric() || **c == '_').count()
+            },
+            _ => 1,
+        };
+        advance(&mut i, &mut line, &mut col, len);
+    }
+
+    out
+}
+
+// whether the char before `i` continues an identifier.
+fn is_ident_char(chars: &[char], i: usize) -> bool {
+    i > 0 && (chars[i-1].is_alphanumeric() || chars[i-1] == '_')
+}
+
+// the length of the (possibly nested) block comment at the start of `s`.
+fn block_comment_len(s: &[char]) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        match (s[i], s.get(i+1)) {
+            ('/', Some('*')) => { depth += 1; i += 2; },
+            ('*', Some('/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 { return i; }
+            },
+            _ => i += 1,
+        }
+    }
+
+    s.len()
+}
+
+// the length of the string literal at the start of `s`.
+fn string_len(s: &[char]) -> usize {
+    let mut i = 1;
+    while i < s.len() {
+        match s[i] {
+            '\\' => i += 2,
+            '"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    s.len()
+}
+
+// the length of the raw string literal `#*"..."#*` at the start of `s`, without the `r` prefix.
+fn raw_string_len(s: &[char]) -> Option<usize> {
+    let hashes = s.iter().take_while(|c| **c == '#').count();
+    if s.get(hashes) != Some(&'"') { return None; }
+
+    let mut i = hashes + 1;
+    while i < s.len() {
+        if s[i] == '"' && s[i+1..].iter().take(hashes).filter(|c| **c == '#').count() == hashes {
+            return Some(i + 1 + hashes);
+        }
+        i += 1;
+    }
+
+    Some(s.len())
+}
+
+// the length of the char literal or lifetime at the start of `s`, which is a `'`.
+fn char_len(s: &[char]) -> usize {
+    match (s.get(1), s.get(2)) {
+        // an escaped char literal, like `'\n'` or `'\u{0}'`.
+        (Some('\\'), _) => s.iter().skip(3).position(|c| *c == '\'').map_or(s.len(), |p| p + 4),
+        // a char literal, like `'a'`.
+        (Some(_), Some('\'')) => 3,
+        // a lifetime, like `'a`.
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the start, text and `own_line` of each comment in `code`.
+    fn comments(code: &str) -> Vec<((usize, usize), String, bool)> {
+        lex(code).into_iter().map(|c| (c.start, c.text, c.own_line)).collect()
+    }
+
+    fn comment(start: (usize, usize), text: &str, own_line: bool) -> ((usize, usize), String, bool) {
+        (start, text.to_string(), own_line)
+    }
+
+    #[test]
+    fn line_comments() {
+        let code = "// a  \nfn f() {\n    x(); // b\n}";
+        assert_eq!(comments(code), [comment((1, 0), "// a", true), comment((3, 9), "// b", false)]);
+
+        // the column counts chars, not bytes.
+        assert_eq!(comments("let s = \"ä\"; // c"), [comment((1, 13), "// c", false)]);
+    }
+
+    #[test]
+    fn block_comments() {
+        let code = "x /* a /* b */ c */ y\n  /* d\n*/";
+        assert_eq!(comments(code), [comment((1, 2), "/* a /* b */ c */", false), comment((2, 2), "/* d\n*/", true)]);
+
+        // an unclosed comment goes until the end.
+        assert_eq!(comments("/* a /* b */"), [comment((1, 0), "/* a /* b */", true)]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(comments(r#"f("// a \" /* b"); // c"#), [comment((1, 19), "// c", false)]);
+        assert_eq!(comments(r###"f(r#"// a " /* b"#); // c"###), [comment((1, 21), "// c", false)]);
+        assert_eq!(comments(r#"f(br"// a", cr"/* b"); // c"#), [comment((1, 23), "// c", false)]);
+
+        // a raw identifier doesn't start a raw string.
+        assert_eq!(comments("let r#fn = r; // a"), [comment((1, 14), "// a", false)]);
+    }
+
+    #[test]
+    fn lifetimes_and_chars() {
+        // a lifetime doesn't start a char literal, which would hide the comment.
+        assert_eq!(comments("fn f<'a>(x: &'a str) {} // a"), [comment((1, 24), "// a", false)]);
+        assert_eq!(comments("g('\"', '/', '\\'', '\\u{2f}'); // a"), [comment((1, 29), "// a", false)]);
+        assert_eq!(comments("fn f(x: &'a u8) -> char { '\"' } // a"), [comment((1, 32), "// a", false)]);
+    }
+
+    #[test]
+    fn doc_comments() {
+        // `syn` keeps doc comments as attributes already.
+        assert_eq!(comments("/// a\n//! b\n/** c */\n/*! d */"), []);
+
+        let code = "//// a\n/**/\n/*** b */";
+        assert_eq!(comments(code), [comment((1, 0), "//// a", true), comment((2, 0), "/**/", true), comment((3, 0), "/*** b */", true)]);
+    }
+}
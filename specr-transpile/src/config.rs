@@ -1,10 +1,24 @@
 use std::path::{Path, PathBuf};
 use std::fs;
 
+use crate::diagnostic::Diagnostic;
+
 pub struct Config {
     /// Whether to run `cargo check` on the generated code.
     pub check: bool,
 
+    /// Whether to keep running, and transpile again whenever the input changes.
+    pub watch: bool,
+
+    /// The names of the passes after which the code is written to the `specr-stages` directory of the output.
+    pub emit_after: Vec<String>,
+
+    /// Whether to write the code after parsing and after every pass to the `specr-stages` directory of the output.
+    pub emit_all_stages: bool,
+
+    /// Whether to write a unified diff for each emitted stage, against the previous stage.
+    pub emit_diff: bool,
+
     /// config root directory.
     pub root: PathBuf,
 
@@ -14,6 +28,23 @@ pub struct Config {
     /// output path, this is where the crate will be constructed.
     pub output: String,
 
+    /// paths of modules and .md files relative to `input`, which are read first in this order.
+    /// All other modules and files follow, sorted by name.
+    pub order: Vec<String>,
+
+    /// Whether subdirectories of a module directory become nested modules.
+    /// Otherwise, all .md files within a top-level directory make up a single module.
+    pub nested_modules: bool,
+
+    /// The names of the passes to run, in this order. If this is `None`, all registered passes run in their default order.
+    pub passes: Option<Vec<String>>,
+
+    /// The names of passes which should not run.
+    pub disabled_passes: Vec<String>,
+
+    /// What `typerec` does about recursive enums and structs lacking a `#[specr::indirection]`.
+    pub auto_indirection: AutoIndirection,
+
     /// extra inner attributes for the generated rust crate.
     pub attrs: Vec<String>,
 
@@ -28,59 +59,127 @@ pub struct Config {
     pub libspecr_path: Option<String>,
 }
 
+/// What to do about enums and structs which contain themselves without an indirection, and thus have infinite size.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AutoIndirection {
+    /// Leave them alone, rustc will complain.
+    Off,
+    /// Report an error, pointing to a field which should get a `#[specr::indirection]`.
+    Report,
+    /// Add `#[specr::indirection]` to such a field.
+    Insert,
+}
+
 impl Config {
-    pub fn load() -> Config {
+    /// A config for the crate `name`, reading from and writing to the current directory, without any flags.
+    pub fn new(name: impl Into<String>) -> Config {
+        Config {
+            check: false,
+            watch: false,
+            emit_after: Vec::new(),
+            emit_all_stages: false,
+            emit_diff: false,
+            root: PathBuf::new(),
+            input: ".".to_string(),
+            output: ".".to_string(),
+            order: Vec::new(),
+            nested_modules: false,
+            passes: None,
+            disabled_passes: Vec::new(),
+            auto_indirection: AutoIndirection::Off,
+            attrs: Vec::new(),
+            channel: None,
+            name: name.into(),
+            libspecr_path: None,
+        }
+    }
+
+    /// Loads the config file given on the command line, together with the flags.
+    pub fn load() -> Result<Config, Diagnostic> {
         let mut args = std::env::args();
         args.next().unwrap(); // skip program name
 
+        let usage = "usage: specr-transpile <specr.toml> [--check] [--watch] [--emit-after=<pass>]... [--emit-all-stages] [--emit-diff]";
         let Some(file) = args.next() else {
-            eprintln!("Usage:");
-            eprintln!("specr-transpile <specr.toml> [--check]");
-            eprintln!("");
-            panic!("invalid amount of command-line arguments!");
+            return Err(Diagnostic::error("invalid amount of command-line arguments").note(usage));
         };
 
-        let check = match args.next() {
-            Some(flag) if flag == "--check" => {
-                if args.next().is_some() {
-                    panic!("too many command-line arguments");
+        let mut check = false;
+        let mut watch = false;
+        let mut emit_after: Vec<String> = Vec::new();
+        let mut emit_all_stages = false;
+        let mut emit_diff = false;
+        for flag in args {
+            if let Some(pass) = flag.strip_prefix("--emit-after=") {
+                if emit_after.iter().any(|p| p == pass) {
+                    return Err(Diagnostic::error(format!("flag `{flag}` given twice")).note(usage));
                 }
-                true
+                emit_after.push(pass.to_string());
+                continue;
             }
-            Some(flag) => {
-                panic!("unknown flag `{flag}`");
+
+            let set = match &*flag {
+                "--check" => &mut check,
+                "--watch" => &mut watch,
+                "--emit-all-stages" => &mut emit_all_stages,
+                "--emit-diff" => &mut emit_diff,
+                _ => return Err(Diagnostic::error(format!("unknown flag `{flag}`")).note(usage)),
+            };
+            if *set {
+                return Err(Diagnostic::error(format!("flag `{flag}` given twice")).note(usage));
             }
-            None => false,
-        };
+            *set = true;
+        }
 
-        let f = fs::canonicalize(file).unwrap();
-        let s = fs::read_to_string(&f).unwrap();
+        let f = fs::canonicalize(&file).map_err(|e| Diagnostic::error(format!("could not find config file `{file}`: {e}")))?;
+        let s = fs::read_to_string(&f).map_err(|e| Diagnostic::error(format!("could not read config file `{file}`: {e}")))?;
         let root = f.parent().unwrap().to_path_buf();
 
-        let table = s.parse::<toml::Table>().unwrap();
-        let input = table.get("input").expect("`input` missing in config file")
-                         .as_str().expect("`input` is no string!").to_string();
-        let output = table.get("output").expect("`output` missing in config file")
-                         .as_str().expect("`output` is no string!").to_string();
-        let attrs = table.get("attrs")
-                          .map(|v| v.clone().try_into().expect("`attrs` is required to be an array of strings!"))
-                          .unwrap_or_else(Vec::new);
-        let channel = table.get("channel")
-                          .map(|v| v.clone().try_into().expect("`channel` is required to be a string!"));
-        let name = table.get("name").expect("`name` is missing in config file")
-                          .clone().try_into().expect("`name` is required to be a string!");
-        let libspecr_path = table.get("libspecr_path").map(|s| s.clone().try_into().expect("`libspecr_path` is required to be a string"));
+        let config = Config::parse(&s, root).map_err(|e| e.note(format!("in config file `{file}`")))?;
 
-        Config {
-            check,
+        Ok(Config { check, watch, emit_after, emit_all_stages, emit_diff, ..config })
+    }
+
+    /// Parses the contents of a config file, see the README.
+    /// Relative paths are relative to `root`, the directory of the config file.
+    pub fn parse(s: &str, root: PathBuf) -> Result<Config, Diagnostic> {
+        let table = s.parse::<toml::Table>().map_err(|e| Diagnostic::error(format!("could not parse config file: {e}")))?;
+        let input = get_str(&table, "input")?.ok_or_else(|| missing("input"))?;
+        let output = get_str(&table, "output")?.ok_or_else(|| missing("output"))?;
+        let order = get_str_list(&table, "order")?.unwrap_or_default();
+        let nested_modules = get_bool(&table, "nested_modules")?.unwrap_or(false);
+        let passes = get_str_list(&table, "passes")?;
+        let disabled_passes = get_str_list(&table, "disable_passes")?.unwrap_or_default();
+        let auto_indirection = match get_str(&table, "auto_indirection")?.as_deref() {
+            None | Some("off") => AutoIndirection::Off,
+            Some("report") => AutoIndirection::Report,
+            Some("insert") => AutoIndirection::Insert,
+            Some(_) => return Err(Diagnostic::error("`auto_indirection` is required to be one of \"off\", \"report\" or \"insert\"")),
+        };
+        let attrs = get_str_list(&table, "attrs")?.unwrap_or_default();
+        let channel = get_str(&table, "channel")?;
+        let name = get_str(&table, "name")?.ok_or_else(|| missing("name"))?;
+        let libspecr_path = get_str(&table, "libspecr_path")?;
+
+        Ok(Config {
+            check: false,
+            watch: false,
+            emit_after: Vec::new(),
+            emit_all_stages: false,
+            emit_diff: false,
             root,
             input,
             output,
+            order,
+            nested_modules,
+            passes,
+            disabled_passes,
+            auto_indirection,
             attrs,
             channel,
             name,
             libspecr_path,
-        }
+        })
     }
 
     pub fn input_path(&self) -> PathBuf {
@@ -101,3 +200,32 @@ impl Config {
         }
     }
 }
+
+fn missing(key: &str) -> Diagnostic {
+    Diagnostic::error(format!("`{key}` is missing in the config file"))
+}
+
+fn get_str(table: &toml::Table, key: &str) -> Result<Option<String>, Diagnostic> {
+    let Some(v) = table.get(key) else { return Ok(None) };
+    match v.as_str() {
+        Some(s) => Ok(Some(s.to_string())),
+        None => Err(Diagnostic::error(format!("`{key}` is required to be a string"))),
+    }
+}
+
+fn get_bool(table: &toml::Table, key: &str) -> Result<Option<bool>, Diagnostic> {
+    let Some(v) = table.get(key) else { return Ok(None) };
+    match v.as_bool() {
+        Some(b) => Ok(Some(b)),
+        None => Err(Diagnostic::error(format!("`{key}` is required to be a boolean"))),
+    }
+}
+
+fn get_str_list(table: &toml::Table, key: &str) -> Result<Option<Vec<String>>, Diagnostic> {
+    let Some(v) = table.get(key) else { return Ok(None) };
+    let err = || Diagnostic::error(format!("`{key}` is required to be an array of strings"));
+    let arr = v.as_array().ok_or_else(err)?;
+    let list = arr.iter().map(|x| x.as_str().map(String::from).ok_or_else(err)).collect::<Result<_, _>>()?;
+
+    Ok(Some(list))
+}
@@ -0,0 +1,123 @@
+//! Errors and warnings found while transpiling, printed in the style of rustc.
+
+use std::fmt::{self, Display};
+
+use crate::prelude::*;
+use crate::source_map::{self, Location};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    /// where in the .md files the problem is.
+    pub location: Option<Location>,
+    /// the source line at `location`.
+    pub snippet: Option<Box<str>>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Level::Error, message.into())
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Level::Warning, message.into())
+    }
+
+    fn new(level: Level, message: String) -> Diagnostic {
+        Diagnostic { level, message, location: None, snippet: None, notes: Vec::new() }
+    }
+
+    /// Points this diagnostic at `span`, if that span stems from a .md file.
+    pub fn at(mut self, span: Span) -> Diagnostic {
+        self.location = source_map::locate(span);
+        self.snippet = source_map::line(span).map(Into::into);
+
+        self
+    }
+
+    /// Points this diagnostic at a given .md location.
+    pub fn at_location(mut self, location: Location, snippet: Option<String>) -> Diagnostic {
+        self.location = Some(location);
+        self.snippet = snippet.map(Into::into);
+
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        writeln!(f, "{level}: {}", self.message)?;
+
+        let gutter = match &self.location {
+            Some(loc) => loc.line.to_string().len(),
+            None => 0,
+        };
+        let pad = " ".repeat(gutter);
+
+        if let Some(loc) = &self.location {
+            writeln!(f, "{pad}--> {loc}")?;
+            if let Some(snippet) = &self.snippet {
+                writeln!(f, "{pad} |")?;
+                writeln!(f, "{} | {}", loc.line, snippet)?;
+                writeln!(f, "{pad} | {}^", " ".repeat(loc.column - 1))?;
+            }
+        }
+        for note in &self.notes {
+            writeln!(f, "{pad} = note: {note}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects the diagnostics of all passes, so that one run can report every problem at once.
+#[derive(Default)]
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn emit(&mut self, d: Diagnostic) {
+        self.list.push(d);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.list.iter().any(|d| d.level == Level::Error)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Diagnostic> {
+        self.list.iter()
+    }
+
+    /// Summarizes the diagnostics like rustc, e.g. "error: aborting due to 2 previous errors".
+    pub fn summary(&self) -> Option<String> {
+        let count = |level| self.list.iter().filter(|d| d.level == level).count();
+        let plural = |n: usize, s: &str| if n == 1 { format!("1 {s}") } else { format!("{n} {s}s") };
+        let errors = count(Level::Error);
+        let warnings = count(Level::Warning);
+        match (errors, warnings) {
+            (0, 0) => None,
+            (0, w) => Some(format!("warning: {} emitted", plural(w, "warning"))),
+            (e, 0) => Some(format!("error: aborting due to {}", plural(e, "previous error"))),
+            (e, w) => Some(format!("error: aborting due to {}; {} emitted", plural(e, "previous error"), plural(w, "warning"))),
+        }
+    }
+}
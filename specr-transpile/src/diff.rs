@@ -0,0 +1,209 @@
+//! Unified diffs between two versions of a file, as printed by `diff -u`.
+
+use std::fmt::Write;
+
+// the number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// Returns the unified diff from `old` to `new`, or an empty string if they are equal.
+/// `name` is the path of the file, printed as `a/<name>` and `b/<name>`.
+pub fn unified(name: &str, old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let edits = edits(&a, &b);
+    if edits.iter().all(|e| *e == Edit::Keep) {
+        return String::new();
+    }
+
+    // the position in `a` and `b` before each edit.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for e in &edits {
+        positions.push((i, j));
+        match e {
+            Edit::Keep => { i += 1; j += 1; },
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let mut out = format!("--- a/{name}\n+++ b/{name}\n");
+    let changed: Vec<usize> = (0..edits.len()).filter(|k| edits[*k] != Edit::Keep).collect();
+    let mut k = 0;
+    while k < changed.len() {
+        // group changes whose contexts overlap into one hunk.
+        let start = changed[k].saturating_sub(CONTEXT);
+        let mut end = changed[k] + 1;
+        while k < changed.len() && changed[k] <= end + 2 * CONTEXT {
+            end = changed[k] + 1;
+            k += 1;
+        }
+        let end = (end + CONTEXT).min(edits.len());
+
+        let (i0, j0) = positions[start];
+        let (i1, j1) = positions[end];
+        // empty ranges are written as the line before them.
+        let range = |from: usize, to: usize| if from == to { format!("{from},0") } else { format!("{},{}", from + 1, to - from) };
+        writeln!(out, "@@ -{} +{} @@", range(i0, i1), range(j0, j1)).unwrap();
+        for e in start..end {
+            let (i, j) = positions[e];
+            match edits[e] {
+                Edit::Keep => writeln!(out, " {}", a[i]),
+                Edit::Delete => writeln!(out, "-{}", a[i]),
+                Edit::Insert => writeln!(out, "+{}", b[j]),
+            }.unwrap();
+        }
+    }
+
+    out
+}
+
+// Computes a shortest edit script from `a` to `b`, following Myers' "An O(ND) Difference Algorithm".
+fn edits(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+
+    // v[k + offset] is the furthest x reached on diagonal k = x - y.
+    let mut v = vec![0isize; 2 * max + 3];
+    // the part of `v` for the diagonals -d-1..=d+1 before each step d, to reconstruct the path.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'outer: for d in 0..=max as isize {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]);
+            let mut x = if down { v[(k + 1 + offset) as usize] } else { v[(k - 1 + offset) as usize] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(k + offset) as usize] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+        }
+    }
+
+    // walk back from the end, collecting the edits in reverse.
+    let mut out = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && get(k - 1) < get(k + 1));
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            out.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            out.push(if down { Edit::Insert } else { Edit::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    out.reverse();
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the lines of `s`, one per character.
+    fn chars(s: &str) -> Vec<String> {
+        s.chars().map(String::from).collect()
+    }
+
+    // checks that the edits from `a` to `b` turn `a` into `b`, and that there are `n` changes.
+    fn check_edits(a: &str, b: &str, n: usize) {
+        let (a, b) = (chars(a), chars(b));
+        let a: Vec<&str> = a.iter().map(|x| x.as_str()).collect();
+        let b: Vec<&str> = b.iter().map(|x| x.as_str()).collect();
+        let edits = edits(&a, &b);
+
+        let (mut i, mut j) = (0, 0);
+        for e in &edits {
+            match e {
+                Edit::Keep => {
+                    assert_eq!(a[i], b[j]);
+                    i += 1;
+                    j += 1;
+                },
+                Edit::Delete => i += 1,
+                Edit::Insert => j += 1,
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()));
+        assert_eq!(edits.iter().filter(|e| **e != Edit::Keep).count(), n);
+    }
+
+    #[test]
+    fn shortest_edits() {
+        check_edits("", "", 0);
+        check_edits("abc", "abc", 0);
+        check_edits("", "abc", 3);
+        check_edits("abc", "", 3);
+        check_edits("abc", "axc", 2);
+        // the example of Myers' paper.
+        check_edits("abcabba", "cbabac", 5);
+        check_edits("abcdefg", "xabdefgy", 3);
+    }
+
+    #[test]
+    fn equal() {
+        assert_eq!(unified("f.rs", "a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+        let new = "1\n2\n3\n4\n5\nsix\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n17\n";
+        let expected = "\
+--- a/f.rs
++++ b/f.rs
+@@ -3,7 +3,7 @@
+ 3
+ 4
+ 5
+-6
++six
+ 7
+ 8
+ 9
+@@ -14,3 +14,4 @@
+ 14
+ 15
+ 16
++17
+";
+        assert_eq!(unified("f.rs", old, new), expected);
+
+        // changes whose contexts overlap are in the same hunk.
+        let new = "1\n2\n3\n4\n5\nsix\n7\n8\n9\n10\n11\nwelve\n13\n14\n15\n16\n";
+        let diff = unified("f.rs", old, new);
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.contains("@@ -3,13 +3,13 @@\n"));
+    }
+
+    #[test]
+    fn empty_ranges() {
+        assert_eq!(unified("f.rs", "", "a\n"), "--- a/f.rs\n+++ b/f.rs\n@@ -0,0 +1,1 @@\n+a\n");
+        assert_eq!(unified("f.rs", "a\nb\n", ""), "--- a/f.rs\n+++ b/f.rs\n@@ -1,2 +0,0 @@\n-a\n-b\n");
+    }
+}
@@ -4,6 +4,14 @@ use crate::prelude::*;
 ///
 /// We do this because the `Index` trait forces us to return a reference;
 /// but we can (should) not return a reference into the GC_STATE.
+///
+/// For the same reason, assignments to indexed places are converted to `set` and `mutate_at` calls:
+/// `a[b] = c` becomes `a.set(b, c)`, and `a[b] += c` or `a[b].x = c` become `a.mutate_at(b, |e| ...)`.
+///
+/// Indexing by a range like `a[b..c]` uses the `subslice`, `write_subslice` and `mutate_subslice` methods of `List` instead.
+///
+/// Items, `let` statements and blocks marked with `#[specr::native_index]` are left alone,
+/// so that they can index native Rust types like `HashMap`.
 pub fn index(mut ast: syn::File) -> syn::File {
     Visitor.visit_file_mut(&mut ast);
 
@@ -13,17 +21,276 @@ pub fn index(mut ast: syn::File) -> syn::File {
 struct Visitor;
 
 impl VisitMut for Visitor {
+    fn visit_item_mut(&mut self, node: &mut Item) {
+        let attrs = match node {
+            Item::Const(x) => &mut x.attrs,
+            Item::Fn(x) => &mut x.attrs,
+            Item::Impl(x) => &mut x.attrs,
+            Item::Mod(x) => &mut x.attrs,
+            Item::Static(x) => &mut x.attrs,
+            Item::Trait(x) => &mut x.attrs,
+            _ => return visit_item_mut(self, node),
+        };
+        if !take_native_index_attr(attrs) {
+            visit_item_mut(self, node);
+        }
+    }
+
+    fn visit_impl_item_mut(&mut self, node: &mut ImplItem) {
+        let attrs = match node {
+            ImplItem::Const(x) => &mut x.attrs,
+            ImplItem::Fn(x) => &mut x.attrs,
+            _ => return visit_impl_item_mut(self, node),
+        };
+        if !take_native_index_attr(attrs) {
+            visit_impl_item_mut(self, node);
+        }
+    }
+
+    fn visit_trait_item_mut(&mut self, node: &mut TraitItem) {
+        let attrs = match node {
+            TraitItem::Const(x) => &mut x.attrs,
+            TraitItem::Fn(x) => &mut x.attrs,
+            _ => return visit_trait_item_mut(self, node),
+        };
+        if !take_native_index_attr(attrs) {
+            visit_trait_item_mut(self, node);
+        }
+    }
+
+    fn visit_local_mut(&mut self, node: &mut Local) {
+        if !take_native_index_attr(&mut node.attrs) {
+            visit_local_mut(self, node);
+        }
+    }
+
     fn visit_expr_mut(&mut self, node: &mut Expr) {
-        if let Expr::Index(idx) = &*node {
-            let lhs = &*idx.expr;
-            let rhs = &*idx.index;
-            let ts = quote! {
-                (#lhs).index_at(#rhs)
-            };
-            let call: ExprMethodCall = parse2(ts).unwrap();
-            *node = call.into();
+        if let Expr::Block(b) = node {
+            if take_native_index_attr(&mut b.attrs) { return; }
+        }
+
+        match &*node {
+            Expr::Assign(a) if has_index(&a.left) => {
+                *node = assign(&a.left, None, &a.right);
+            },
+            Expr::Binary(b) if is_assign_op(&b.op) && has_index(&b.left) => {
+                *node = assign(&b.left, Some(&b.op), &b.right);
+            },
+            Expr::Index(idx) => {
+                let lhs = &*idx.expr;
+                let rhs = &*idx.index;
+                let ts = if is_range(rhs) {
+                    let rhs = index_arg(rhs);
+                    quote! { (#lhs).subslice(#rhs) }
+                } else {
+                    quote! { (#lhs).index_at(#rhs) }
+                };
+                let call: ExprMethodCall = parse2(ts).unwrap();
+                *node = call.into();
+            },
+            _ => {},
         }
 
         visit_expr_mut(self, node);
     }
 }
+
+// removes the `#[specr::native_index]` attributes from `attrs`, returning whether there were any.
+fn take_native_index_attr(attrs: &mut Vec<Attribute>) -> bool {
+    let is_native_index = |attr: &Attribute| format!("{}", attr.path().to_token_stream()).replace(' ', "") == "specr::native_index";
+    let len = attrs.len();
+    attrs.retain(|attr| !is_native_index(attr));
+
+    attrs.len() != len
+}
+
+pub(crate) fn is_assign_op(op: &BinOp) -> bool {
+    matches!(op,
+        BinOp::AddAssign(_) | BinOp::SubAssign(_) | BinOp::MulAssign(_) | BinOp::DivAssign(_) | BinOp::RemAssign(_) |
+        BinOp::BitXorAssign(_) | BinOp::BitAndAssign(_) | BinOp::BitOrAssign(_) | BinOp::ShlAssign(_) | BinOp::ShrAssign(_)
+    )
+}
+
+// whether the place `e` is (a field of) an indexed place.
+fn has_index(e: &Expr) -> bool {
+    match e {
+        Expr::Index(_) => true,
+        Expr::Field(f) => has_index(&f.base),
+        Expr::Paren(p) => has_index(&p.expr),
+        _ => false,
+    }
+}
+
+// lowers `place = value`, or `place op= value` if `op` is given.
+fn assign(place: &Expr, op: Option<&BinOp>, value: &Expr) -> Expr {
+    if let (None, Expr::Index(idx)) = (op, strip_parens(place)) {
+        // `a[b] = c` doesn't need to read `a[b]`, it just sets it.
+        if !has_index(&idx.expr) {
+            let (a, b) = (&idx.expr, index_arg(&idx.index));
+            let set = setter(&idx.index);
+            return parse_quote! { (#a).#set(#b, #value) };
+        }
+    }
+
+    // The value and the indices are evaluated into variables first,
+    // as the closures passed to `mutate_at` may not read the list or map which is being mutated.
+    let mut lets = Vec::new();
+    let val = format_ident!("__specr_val");
+    let stmt = match (op, strip_parens(place)) {
+        (None, Expr::Index(idx)) => {
+            let set = setter(&idx.index);
+            let i = bind(&index_arg(&idx.index), &mut lets);
+            lower(&idx.expr, &mut lets, &|p| quote! { (#p).#set(#i, #val) })
+        },
+        (None, place) => lower(place, &mut lets, &|p| quote! { #p = #val }),
+        (Some(op), place) => lower(place, &mut lets, &|p| quote! { #p #op #val }),
+    };
+
+    parse_quote! {
+        {
+            #( #lets )*
+            let #val = #value;
+            #stmt;
+        }
+    }
+}
+
+// calls `action` with the Rust expression for the place `place`,
+// wrapping it in `mutate_at` calls for every index within the place.
+fn lower(place: &Expr, lets: &mut Vec<TokenStream>, action: &dyn Fn(TokenStream) -> TokenStream) -> TokenStream {
+    match strip_parens(place) {
+        Expr::Index(idx) => {
+            let mutate = if is_range(&idx.index) { format_ident!("mutate_subslice") } else { format_ident!("mutate_at") };
+            let i = bind(&index_arg(&idx.index), lets);
+            let elem = format_ident!("__specr_elem");
+            let inner = action(quote! { *#elem });
+            lower(&idx.expr, lets, &|p| quote! { (#p).#mutate(#i, |#elem| { #inner; }) })
+        },
+        Expr::Field(f) if has_index(&f.base) => {
+            let member = &f.member;
+            lower(&f.base, lets, &|p| action(quote! { (#p).#member }))
+        },
+        place => action(place.to_token_stream()),
+    }
+}
+
+// evaluates the index `e` into a fresh variable, and returns its name.
+// The places are lowered from the inside out, so the outer indices are inserted in front to evaluate them first.
+pub(crate) fn bind(e: &Expr, lets: &mut Vec<TokenStream>) -> Ident {
+    let name = format_ident!("__specr_idx{}", lets.len());
+    lets.insert(0, quote! { let #name = #e; });
+
+    name
+}
+
+// the index `i` as argument of `set` or `mutate_at`, or of the `subslice` methods if it is a range.
+// `List` takes `Int` indices, but native slices take `usize` ones, so the index is converted with `into`.
+// Integer literals are made `usize`s for that, as they would default to `i32` otherwise.
+// The endpoints of ranges are converted the same way, which `ListRange` allows to infer.
+pub(crate) fn index_arg(i: &Expr) -> Expr {
+    match strip_parens(i) {
+        Expr::Range(r) => {
+            let mut r = r.clone();
+            for e in r.start.iter_mut().chain(r.end.iter_mut()) {
+                **e = index_arg(e);
+            }
+            r.into()
+        },
+        Expr::Lit(ExprLit { lit: Lit::Int(lit), .. }) if lit.suffix().is_empty() => {
+            let lit = LitInt::new(&format!("{}usize", lit.base10_digits()), lit.span());
+            parse_quote! { (#lit).into() }
+        },
+        i => parse_quote! { (#i).into() },
+    }
+}
+
+// the method setting the index `i`.
+fn setter(i: &Expr) -> Ident {
+    if is_range(i) { format_ident!("write_subslice") } else { format_ident!("set") }
+}
+
+// whether the index `i` is a range, like in `a[b..c]`.
+pub(crate) fn is_range(i: &Expr) -> bool {
+    matches!(strip_parens(i), Expr::Range(_))
+}
+
+pub(crate) fn strip_parens(e: &Expr) -> &Expr {
+    match e {
+        Expr::Paren(p) => strip_parens(&p.expr),
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lowers the statements `input` in a function body, and compares them with `expected`.
+    fn check(input: &str, expected: &str) {
+        let input: syn::File = parse_str(&format!("fn f() {{ {input} }}")).unwrap();
+        let expected: syn::File = parse_str(&format!("fn f() {{ {expected} }}")).unwrap();
+        assert_eq!(index(input).to_token_stream().to_string(), expected.to_token_stream().to_string());
+    }
+
+    #[test]
+    fn read() {
+        check("x = a[b];", "x = (a).index_at(b);");
+        check("x = a[b..c];", "x = (a).subslice((b).into()..(c).into());");
+        check("x = a[1..];", "x = (a).subslice((1usize).into()..);");
+        check("x = a[..=n];", "x = (a).subslice(..=(n).into());");
+        check("x = a[..];", "x = (a).subslice(..);");
+        check("a[i] = b[j];", "(a).set((i).into(), (b).index_at(j));");
+    }
+
+    #[test]
+    fn set() {
+        check("a[b] = c;", "(a).set((b).into(), c);");
+        // integer literals would be inferred as `i32` otherwise.
+        check("a[0] = c;", "(a).set((0usize).into(), c);");
+        check("a[1..] = c;", "(a).write_subslice((1usize).into().., c);");
+    }
+
+    #[test]
+    fn mutate() {
+        check("a[i] += c;", "{
+            let __specr_idx0 = (i).into();
+            let __specr_val = c;
+            (a).mutate_at(__specr_idx0, |__specr_elem| { *__specr_elem += __specr_val; });
+        };");
+        check("(a[i]).x.y -= c;", "{
+            let __specr_idx0 = (i).into();
+            let __specr_val = c;
+            (a).mutate_at(__specr_idx0, |__specr_elem| { ((*__specr_elem).x).y -= __specr_val; });
+        };");
+    }
+
+    #[test]
+    fn field() {
+        check("a[i].x = c;", "{
+            let __specr_idx0 = (i).into();
+            let __specr_val = c;
+            (a).mutate_at(__specr_idx0, |__specr_elem| { (*__specr_elem).x = __specr_val; });
+        };");
+    }
+
+    #[test]
+    fn nested() {
+        check("g[i][j] = c;", "{
+            let __specr_idx1 = (i).into();
+            let __specr_idx0 = (j).into();
+            let __specr_val = c;
+            (g).mutate_at(__specr_idx1, |__specr_elem| { (*__specr_elem).set(__specr_idx0, __specr_val); });
+        };");
+        check("a[1..][j] = c;", "{
+            let __specr_idx1 = (1usize).into()..;
+            let __specr_idx0 = (j).into();
+            let __specr_val = c;
+            (a).mutate_subslice(__specr_idx1, |__specr_elem| { (*__specr_elem).set(__specr_idx0, __specr_val); });
+        };");
+    }
+
+    #[test]
+    fn native_index() {
+        check("#[specr::native_index] let x = a[b];", "let x = a[b];");
+    }
+}
@@ -0,0 +1,389 @@
+//! specr-transpile converts specr lang code to Rust code.
+//!
+//! Use [`run`] to transpile the `input` directory of a [`Config`] to its `output` directory,
+//! or [`transpile`] to transpile .md files in memory.
+
+// TODO consistent module naming scheme for module and entry function.
+mod argmatch;
+mod comments;
+mod markdown;
+mod merge_impls;
+mod source;
+mod source_map;
+mod typerec;
+mod auto_derive;
+mod auto_obj_bound;
+mod index;
+mod resolve;
+
+mod config;
+mod diagnostic;
+mod pass;
+mod stages;
+mod diff;
+mod watch;
+
+pub use config::{AutoIndirection, Config};
+pub use diagnostic::{Diagnostic, Diagnostics, Level};
+pub use pass::{Pass, Registry};
+pub use source::{Cache, Module};
+pub use source_map::Location;
+pub use watch::watch;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use source_map::FileMap;
+
+mod prelude {
+    pub use crate::source::Module;
+    pub use crate::config::Config;
+    pub use crate::diagnostic::{Diagnostic, Diagnostics};
+
+    pub use std::collections::HashSet;
+    pub use quote::{quote, format_ident, ToTokens};
+    pub use syn::*;
+    pub use syn::visit_mut::*;
+    pub use syn::visit::*;
+    pub use proc_macro2::{TokenStream, TokenTree, Span};
+    pub use syn::punctuated::Punctuated;
+}
+use prelude::*;
+
+fn exists<T: AsRef<Path>>(t: T) -> bool {
+    t.as_ref().exists()
+}
+
+fn mkdir<T: AsRef<Path>>(t: T, diags: &mut Diagnostics) {
+    let name = t.as_ref();
+    if !exists(name) {
+        if let Err(e) = fs::create_dir_all(name) {
+            diags.emit(Diagnostic::error(format!("could not create directory `{}`: {e}", name.display())));
+        }
+    }
+}
+
+// writes `contents` to `t`, unless it already contains exactly that.
+// This keeps the modification time of unchanged files, so that cargo doesn't rebuild needlessly.
+fn write<T: AsRef<Path>>(t: T, contents: &str, diags: &mut Diagnostics) {
+    let name = t.as_ref();
+    if fs::read(name).map_or(false, |old| old == contents.as_bytes()) {
+        return;
+    }
+    if let Err(e) = fs::write(name, contents) {
+        diags.emit(Diagnostic::error(format!("could not write `{}`: {e}", name.display())));
+    }
+}
+
+/// The result of one run of specr-transpile.
+pub struct Outcome {
+    /// the rendered diagnostics of specr-transpile and `cargo check`.
+    pub messages: Vec<String>,
+    /// a summary of the diagnostics of specr-transpile, see `Diagnostics::summary`.
+    pub summary: Option<String>,
+    pub success: bool,
+}
+
+impl Outcome {
+    fn new(diags: &Diagnostics) -> Outcome {
+        Outcome {
+            messages: diags.iter().map(|d| d.to_string()).collect(),
+            summary: diags.summary(),
+            success: !diags.has_errors(),
+        }
+    }
+}
+
+/// The generated crate, as returned by [`transpile`].
+pub struct Transpiled {
+    /// the files of the generated crate, by their path within the crate, like `src/lib.rs`.
+    /// This is empty if there were any errors.
+    pub files: Vec<(String, String)>,
+    pub diagnostics: Diagnostics,
+    /// the code after the stages requested by `emit_after` and `emit_all_stages` of the config,
+    /// by their path within the output, like `specr-stages/01-argmatch/lang/mod.rs`.
+    /// These are also returned if there were errors, to help debugging them.
+    pub stages: Vec<(String, String)>,
+    // the source maps of the module files, by their path within the crate.
+    maps: HashMap<String, FileMap>,
+}
+
+/// Transpiles the .md files `inputs` using the passes of `passes`, without touching the file system.
+/// The paths of `inputs` are relative to the `input` directory of `config`, like `lang/values.md`,
+/// and are reported relative to `config.input_path()` in diagnostics.
+/// `check`, `watch` and `output` of `config` are ignored.
+pub fn transpile(config: &Config, passes: &Registry, inputs: Vec<(PathBuf, String)>) -> Transpiled {
+    transpile_cached(config, passes, inputs, &mut Cache::default(), Diagnostics::default())
+}
+
+// like `transpile`, but reuses the modules parsed by previous runs from `cache`,
+// and reports the diagnostics in `diags` as well.
+fn transpile_cached(config: &Config, passes: &Registry, inputs: Vec<(PathBuf, String)>, cache: &mut Cache, mut diags: Diagnostics) -> Transpiled {
+    let pipeline = match passes.pipeline(config) {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            diags.emit(err);
+            return Transpiled { files: Vec::new(), diagnostics: diags, stages: Vec::new(), maps: HashMap::new() };
+        },
+    };
+
+    let mods = source::fetch(config, inputs, cache, &mut diags);
+    // the modules of earlier runs are not needed anymore, unless they were taken from `cache`.
+    source_map::retain(&mods);
+    let lib = create_lib(&mods, config, &mut diags);
+    let (mut files, stages, maps) = compile(mods, &pipeline, config, &mut diags);
+    if diags.has_errors() {
+        return Transpiled { files: Vec::new(), diagnostics: diags, stages, maps };
+    }
+
+    files.insert(0, ("Cargo.toml".to_string(), create_cargo_toml(config)));
+    if let Some(toolchain) = create_rust_toolchain(config) {
+        files.insert(1, ("rust-toolchain.toml".to_string(), toolchain));
+    }
+    files.push(("src/lib.rs".to_string(), lib));
+
+    Transpiled { files, diagnostics: diags, stages, maps }
+}
+
+/// Transpiles the `input` directory of `config` to the crate in its `output` directory using the passes of `passes`,
+/// and runs `cargo check` on it if requested.
+/// `cache` allows to reuse the modules parsed by previous runs.
+pub fn run(config: &Config, passes: &Registry, cache: &mut Cache) -> Outcome {
+    let mut diags = Diagnostics::default();
+
+    if !exists(config.input_path()) {
+        diags.emit(Diagnostic::error(format!("input `{}` not found", &config.input)));
+        return Outcome::new(&diags);
+    }
+
+    let inputs = source::read_input(&config.input_path(), &mut diags);
+    let Transpiled { files, diagnostics: mut diags, stages, maps } = transpile_cached(config, passes, inputs, cache, diags);
+
+    // the stages are written even if transpiling failed, as they help to find out why.
+    if !stages.is_empty() {
+        let dir = config.output_path().join(stages::DIR);
+        // remove the stages of previous runs, which might have had other passes.
+        if exists(&dir) {
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                diags.emit(Diagnostic::error(format!("could not remove directory `{}`: {e}", dir.display())));
+            }
+        }
+        write_files(config, stages, &mut diags);
+    }
+    if diags.has_errors() {
+        return Outcome::new(&diags);
+    }
+
+    // only write the crate once we know that transpiling succeeded.
+    write_files(config, files, &mut diags);
+
+    let mut outcome = Outcome::new(&diags);
+    if outcome.success && config.check {
+        let (messages, success) = check(config, &maps);
+        outcome.messages.extend(messages);
+        outcome.success = success;
+    }
+
+    outcome
+}
+
+// writes `files` to the output, by their path within it.
+fn write_files(config: &Config, files: Vec<(String, String)>, diags: &mut Diagnostics) {
+    for (name, code) in files {
+        let path = config.output_path().join(name);
+        // nested modules are placed in subdirectories.
+        mkdir(path.parent().unwrap(), diags);
+        write(path, &code, diags);
+    }
+}
+
+fn create_cargo_toml(config: &Config) -> String {
+    let package_name = &config.name;
+    let libspecr = match &config.libspecr_path {
+        None => format!("\"={}\"", env!("CARGO_PKG_VERSION")),
+        Some(path) => format!("{{ path = \"{}\" }}", path),
+    };
+    format!("[package]\n\
+                name = \"{name}\"\n\
+                version = \"0.1.0\"\n\
+                edition = \"2024\"\n\
+                \n\
+                [dependencies]\n\
+                libspecr = {libspecr}\n\
+                serde = {{version = \"1.0\", features = [\"derive\"]}}\n\
+               ", name = package_name)
+}
+
+fn create_rust_toolchain(config: &Config) -> Option<String> {
+    let channel = config.channel.as_ref()?;
+
+    Some(format!("[toolchain]\nchannel = \"{channel}\""))
+}
+
+// returns the code of `lib.rs`.
+// The top-level modules are declared here, nested modules are declared by their parent, see `submodules`.
+fn create_lib(mods: &[Module], config: &Config, diags: &mut Diagnostics) -> String {
+    let mods = submodules(mods, &[]);
+
+    let attrs = parse_str::<syn::File>(&config.attrs.join("\n")).unwrap_or_else(|e| {
+        diags.emit(Diagnostic::error(format!("invalid `attrs` in the config file: {e}")));
+        parse_quote!()
+    });
+
+    let code = quote! {
+        #attrs
+
+        #[allow(unused_imports)]
+        #[macro_use] pub extern crate libspecr;
+        #( #mods )*
+    };
+    let code = parse_str::<syn::File>(&code.to_string()).unwrap();
+    prettyplease::unparse(&code)
+}
+
+// returns the declarations of the modules directly within the module `path`.
+fn submodules(mods: &[Module], path: &[String]) -> Vec<Item> {
+    mods.iter()
+        .filter(|x| x.path == path)
+        .map(|x| {
+            let name = format_ident!("{}", x.name);
+            parse_quote! { #[allow(unused_imports)] #[macro_use] pub mod #name; }
+        })
+        .collect()
+}
+
+// files by their path within the output, and their contents.
+type Files = Vec<(String, String)>;
+
+// returns the generated files, the emitted stages and the source maps of the module files, all indexed by their path within the crate.
+fn compile(mut mods: Vec<Module>, pipeline: &[&dyn Pass], config: &Config, diags: &mut Diagnostics) -> (Files, Files, HashMap<String, FileMap>) {
+    let mut stages = stages::Stages::new(config, &mods);
+    for pass in pipeline {
+        stages.before(pass.name(), &mods);
+        mods = pass.run(mods, config, diags);
+        stages.after(pass.name(), &mods);
+    }
+    let stages = stages.files;
+
+    let decls: Vec<Vec<Item>> = mods.iter().map(|m| submodules(&mods, &m.full_path())).collect();
+
+    let mut files = Vec::new();
+    let mut maps = HashMap::new();
+    let mut map_file = String::new();
+    for (m, decls) in mods.into_iter().zip(decls) {
+        let filename = m.file();
+        let mut ast = m.ast;
+
+        // declare the nested modules.
+        ast.items.splice(0..0, decls);
+
+        // write AST back to Rust file.
+        let (code, map) = source_map::unparse(&ast);
+        map.write(&filename, &config.input_path(), &mut map_file);
+        files.push((filename.clone(), code));
+        maps.insert(filename, map);
+    }
+    files.push(("specr-source-map.txt".to_string(), map_file));
+
+    (files, stages, maps)
+}
+
+// runs `cargo check`, returning its diagnostics relocated to the .md files, and whether it succeeded.
+fn check(config: &Config, maps: &HashMap<String, FileMap>) -> (Vec<String>, bool) {
+    let mut cmd = Command::new("cargo");
+    cmd.current_dir(config.output_path());
+    cmd.arg("check");
+    cmd.arg("--message-format=json");
+    if config.watch {
+        // don't repeat the "Checking" status lines on every change.
+        cmd.arg("--quiet");
+    }
+    cmd.stdout(Stdio::piped());
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            let err = Diagnostic::error(format!("could not run `cargo check`: {e}"));
+            return (vec![err.to_string()], false);
+        },
+    };
+
+    let mut messages = Vec::new();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    for line in stdout.lines() {
+        let Ok(line) = line else { break };
+        let Ok(msg) = serde_json::from_str::<serde_json::Value>(&line) else { continue };
+        if msg["reason"] != "compiler-message" { continue; }
+        let Some(rendered) = msg["message"]["rendered"].as_str() else { continue };
+        messages.push(source_map::relocate(rendered.trim_end(), maps));
+    }
+
+    // Cargo already printed an error, we just forward the failed status code.
+    let success = child.wait().map_or(false, |status| status.success());
+
+    (messages, success)
+}
+
+// transpiles the .md files `mods`, given by their path like `lang/a.md` and the Rust code in them,
+// together with a prelude, using the default config and passes. The tests of the passes use this.
+#[cfg(test)]
+fn transpile_rust(mods: &[(&str, &str)]) -> Transpiled {
+    let prelude = ("prelude/prelude.md", "pub use libspecr::prelude::*;");
+    let inputs = std::iter::once(&prelude).chain(mods)
+        .map(|(file, code)| (PathBuf::from(file), format!("```rust\n{code}\n```\n")))
+        .collect();
+    transpile(&Config::new("test"), &Registry::default(), inputs)
+}
+
+#[cfg(test)]
+impl Transpiled {
+    // the code of the generated file `path`, like `src/lang.rs`, without any whitespace.
+    fn code(&self, path: &str) -> String {
+        let (_, code) = self.files.iter().find(|(p, _)| p == path).expect("no such file");
+        code.split_whitespace().collect()
+    }
+
+    // the messages of the diagnostics.
+    fn messages(&self) -> Vec<&str> {
+        self.diagnostics.iter().map(|d| &*d.message).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the location of the only diagnostic of `t`, like `lang/a.md:3`.
+    fn location(t: &Transpiled) -> Option<String> {
+        let d: Vec<&Diagnostic> = t.diagnostics.iter().collect();
+        assert_eq!(d.len(), 1);
+        d[0].location.as_ref().map(|l| format!("{}:{}", l.file.display(), l.line))
+    }
+
+    // Watch mode reuses the cache across runs, while modules are renamed and files are read again.
+    #[test]
+    fn cached_runs() {
+        let prelude = (PathBuf::from("prelude/p.md"), "```rust\npub use libspecr::prelude::*;\n```\n".to_string());
+        // the argmatch pass reports the missing cases, locating the declaration with the source map.
+        let inputs = |module: &str| vec![
+            prelude.clone(),
+            (PathBuf::from(format!("{module}/a.md")), "# A\n\n```rust\n#[specr::argmatch(n)]\npub fn g(n: u8) -> u8;\n```\n".to_string()),
+        ];
+        let config = Config::new("test");
+        let mut cache = Cache::default();
+        let mut run = |module: &str| transpile_cached(&config, &Registry::default(), inputs(module), &mut cache, Diagnostics::default());
+
+        assert_eq!(location(&run("lang")).as_deref(), Some("lang/a.md:5"));
+        let count = source_map::count();
+        assert_eq!(location(&run("lang")).as_deref(), Some("lang/a.md:5"));
+        assert_eq!(source_map::count(), count);
+
+        // renaming a module back has to parse it again, as its code was dropped from the source map.
+        assert_eq!(location(&run("renamed")).as_deref(), Some("renamed/a.md:5"));
+        assert_eq!(source_map::count(), count);
+        assert_eq!(location(&run("lang")).as_deref(), Some("lang/a.md:5"));
+        assert_eq!(source_map::count(), count);
+    }
+}
@@ -0,0 +1,290 @@
+//! Finds the fenced code blocks of a markdown document, following CommonMark.
+//!
+//! A fence is a line of at least three backticks or tildes, indented by at most three spaces
+//! relative to the list item containing it (if any). It is closed by a fence of the same character
+//! and at least the same length, or by the end of its list item.
+//! The indentation of the opening fence is removed from all lines of code.
+//!
+//! The prose before a code block, up to the previous code block or heading, is kept for documentation.
+//!
+//! Block quotes and HTML blocks are not considered.
+
+/// A fenced code block.
+pub struct Fenced {
+    /// the (1-based) line of the opening fence.
+    pub fence_line: usize,
+    /// the indentation of the opening fence, in columns.
+    /// This much indentation was removed from each line of `code`.
+    pub indent: usize,
+    /// everything after the opening fence, trimmed, e.g. `rust,ignore`.
+    pub info: String,
+    pub code: String,
+    /// the paragraphs directly before this block, see `paragraphs`.
+    pub prose: String,
+    /// whether the block was closed before the end of the document.
+    pub closed: bool,
+}
+
+/// Returns all fenced code blocks of the markdown document `s`.
+pub fn fenced_blocks(s: &str) -> Vec<Fenced> {
+    let lines: Vec<&str> = s.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).collect();
+
+    let mut out = Vec::new();
+    // the lines since the last code block or heading.
+    let mut prose = Vec::new();
+    // the content indentation of the list item we are in.
+    let mut list_indent: Option<usize> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let (indent, mut rest) = indentation(lines[i]);
+        i += 1;
+        let fence_line = i;
+
+        // blank lines don't end list items.
+        if rest.is_empty() {
+            prose.push(lines[i-1]);
+            continue;
+        }
+
+        let mut base = match list_indent {
+            Some(li) if indent >= li => li,
+            _ => {
+                list_indent = None;
+                0
+            },
+        };
+        let mut fence_indent = indent;
+        if indent < base + 4 {
+            if let Some((width, after)) = list_marker(rest) {
+                // the content of a list item starts after its marker.
+                base = indent + width;
+                list_indent = Some(base);
+                fence_indent = base;
+                rest = after;
+            }
+        }
+
+        // more indentation would make this an indented code block.
+        let fence = if fence_indent > base + 3 { None } else { opening_fence(rest) };
+        let Some((ch, len, info)) = fence else {
+            if indent <= 3 && is_heading(rest) {
+                prose.clear();
+            } else {
+                prose.push(lines[i-1]);
+            }
+            continue;
+        };
+
+        let mut code = Vec::new();
+        let mut closed = false;
+        while i < lines.len() {
+            let (li, lrest) = indentation(lines[i]);
+            if !lrest.is_empty() && li < base {
+                // the end of the list item also ends the code block.
+                closed = true;
+                break;
+            }
+            i += 1;
+            if li < base + 4 && is_closing_fence(lrest, ch, len) {
+                closed = true;
+                break;
+            }
+            code.push(strip_indentation(lines[i-1], fence_indent));
+        }
+
+        out.push(Fenced {
+            fence_line,
+            indent: fence_indent,
+            info: info.to_string(),
+            code: code.join("\n"),
+            prose: paragraphs(&prose),
+            closed,
+        });
+        prose.clear();
+    }
+
+    out
+}
+
+/// Returns the text of the markdown document `s` before its first fenced code block, see `paragraphs`.
+pub fn intro(s: &str) -> String {
+    let end = fenced_blocks(s).first().map_or(usize::MAX, |b| b.fence_line);
+    let lines: Vec<&str> = s.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).take(end - 1).collect();
+
+    paragraphs(&lines)
+}
+
+// joins `lines` without leading and trailing blank lines, and without their common indentation.
+fn paragraphs(lines: &[&str]) -> String {
+    let is_blank = |l: &&str| l.trim().is_empty();
+    let start = lines.iter().position(|l| !is_blank(l)).unwrap_or(lines.len());
+    let end = lines.iter().rposition(|l| !is_blank(l)).map_or(start, |e| e + 1);
+    let lines = &lines[start..end];
+
+    let indent = lines.iter().filter(|l| !is_blank(l)).map(|l| indentation(l).0).min().unwrap_or(0);
+    lines.iter()
+        .map(|l| strip_indentation(l.trim_end(), indent))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// the width of a tab stop.
+const TAB: usize = 4;
+
+// returns the indentation of `line` in columns, and the rest of the line.
+fn indentation(line: &str) -> (usize, &str) {
+    let mut cols = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            ' ' => cols += 1,
+            '\t' => cols += TAB - cols % TAB,
+            _ => return (cols, &line[i..]),
+        }
+    }
+
+    (cols, "")
+}
+
+// removes up to `n` columns of indentation.
+fn strip_indentation(line: &str, n: usize) -> String {
+    let mut cols = 0;
+    for (i, c) in line.char_indices() {
+        if cols >= n {
+            return line[i..].to_string();
+        }
+        match c {
+            ' ' => cols += 1,
+            '\t' => cols += TAB - cols % TAB,
+            _ => return line[i..].to_string(),
+        }
+    }
+
+    String::new()
+}
+
+// checks whether `s` starts with a list marker like `-`, `*`, `+`, `1.` or `1)`.
+// returns the width of the marker including the following spaces, and the content after that.
+fn list_marker(s: &str) -> Option<(usize, &str)> {
+    let digits = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+    let marker = match s.as_bytes().first()? {
+        b'-' | b'*' | b'+' => 1,
+        _ if (1..=9).contains(&digits) && matches!(s.as_bytes().get(digits), Some(b'.' | b')')) => digits + 1,
+        _ => return None,
+    };
+
+    let after = &s[marker..];
+    let spaces = after.bytes().take_while(|b| *b == b' ').count();
+    // a list item's content starts after at least one space, and at most 4 count as part of the marker.
+    if spaces == 0 && !after.is_empty() {
+        return None;
+    }
+    let spaces = if (1..=4).contains(&spaces) { spaces } else { 1.min(after.len()) };
+
+    Some((marker + spaces, &after[spaces..]))
+}
+
+// checks whether `s` (without indentation) is an ATX heading, like `## Types`.
+fn is_heading(s: &str) -> bool {
+    let n = s.bytes().take_while(|b| *b == b'#').count();
+
+    (1..=6).contains(&n) && s[n..].chars().next().map_or(true, char::is_whitespace)
+}
+
+// checks whether `s` (without indentation) is an opening fence.
+// returns the fence character, the fence length and the info string.
+fn opening_fence(s: &str) -> Option<(char, usize, &str)> {
+    let ch = s.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = s.chars().take_while(|c| *c == ch).count();
+    if len < 3 { return None; }
+
+    let info = s[len..].trim();
+    // the info string of a backtick fence may not contain backticks.
+    if ch == '`' && info.contains('`') { return None; }
+
+    Some((ch, len, info))
+}
+
+// checks whether `s` (without indentation) closes a fence of `len` times `ch`.
+fn is_closing_fence(s: &str, ch: char, len: usize) -> bool {
+    let n = s.chars().take_while(|c| *c == ch).count();
+
+    n >= len && s[n..].trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the info string and code of each block of `s`.
+    fn blocks(s: &str) -> Vec<(String, String)> {
+        fenced_blocks(s).into_iter().map(|b| (b.info, b.code)).collect()
+    }
+
+    fn block(info: &str, code: &str) -> (String, String) {
+        (info.to_string(), code.to_string())
+    }
+
+    #[test]
+    fn info_strings() {
+        let s = "```rust\na\n```\n``` rust,ignore \nb\n```\n```\nc\n```";
+        assert_eq!(blocks(s), [block("rust", "a"), block("rust,ignore", "b"), block("", "c")]);
+
+        // the info string of a backtick fence can't contain backticks, that of a tilde fence can.
+        assert_eq!(blocks("```a`b\nx"), []);
+        assert_eq!(blocks("~~~a`b\nx\n~~~"), [block("a`b", "x")]);
+    }
+
+    #[test]
+    fn tilde_fences() {
+        let s = "~~~rust\n```\nx\n~~~\n";
+        assert_eq!(blocks(s), [block("rust", "```\nx")]);
+
+        // a fence is only closed by the same character.
+        let b = &fenced_blocks("```\nx\n~~~\n")[0];
+        assert_eq!(b.code, "x\n~~~\n");
+        assert!(!b.closed);
+    }
+
+    #[test]
+    fn closing_fences() {
+        // the closing fence has to be at least as long as the opening one, and can't have an info string.
+        let s = "````rust\n```\n```rust\nx\n`````\ny";
+        assert_eq!(blocks(s), [block("rust", "```\n```rust\nx")]);
+        assert_eq!(blocks("```\nx\n``` y\n```"), [block("", "x\n``` y")]);
+
+        let b = &fenced_blocks("```rust\nx\n``")[0];
+        assert_eq!(b.code, "x\n``");
+        assert!(!b.closed);
+    }
+
+    #[test]
+    fn indented_fences() {
+        // the indentation of the opening fence is removed from the code.
+        let b = &fenced_blocks("  ```rust\n  a\n    b\n c\n ```")[0];
+        assert_eq!((b.indent, b.code.as_str(), b.closed), (2, "a\n  b\nc", true));
+
+        // with four spaces, it is an indented code block.
+        assert_eq!(blocks("    ```rust\n    x"), []);
+        // tabs count up to the next tab stop.
+        assert_eq!(blocks("\t```rust\nx"), []);
+    }
+
+    #[test]
+    fn list_items() {
+        let s = "1. Step:\n\n   ```rust\n   x\n   ```\n- ```\n  y\n\n  z\nw";
+        let b = fenced_blocks(s);
+        assert_eq!((b[0].indent, b[0].code.as_str()), (3, "x"));
+        // the end of the list item closes the block.
+        assert_eq!((b[1].indent, b[1].code.as_str(), b[1].closed), (2, "y\n\nz", true));
+        assert_eq!(b.len(), 2);
+    }
+
+    #[test]
+    fn prose() {
+        let s = "# Intro\n\nAbout the module.\n\n## Types\n\nThe type:\n\n```rust\nx\n```\nMore.\n```rust\ny\n```";
+        let b = fenced_blocks(s);
+        assert_eq!((b[0].fence_line, b[0].prose.as_str()), (9, "The type:"));
+        assert_eq!(b[1].prose, "More.");
+        assert_eq!(intro(s), "# Intro\n\nAbout the module.\n\n## Types\n\nThe type:");
+    }
+}
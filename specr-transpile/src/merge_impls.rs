@@ -13,7 +13,14 @@ pub fn merge(mut ast: syn::File) -> syn::File {
     for i in 0..n {
         for j in (i+1)..n {
             if belong_together(&*ii_list[i], &*ii_list[j]) {
-                let tmp = ii_list[j].items.split_off(0); // remove all items from ii_list[j]
+                let mut tmp = ii_list[j].items.split_off(0); // remove all items from ii_list[j]
+
+                // the documentation of ii_list[j] would get lost, so it documents its first item instead.
+                let docs = ii_list[j].attrs.iter().filter(|a| a.path().is_ident("doc")).cloned();
+                if let Some(attrs) = tmp.first_mut().and_then(impl_item_attrs) {
+                    attrs.splice(0..0, docs);
+                }
+
                 ii_list[i].items.extend(tmp);
             }
         }
@@ -40,3 +47,12 @@ fn belong_together(ii1: &ItemImpl, ii2: &ItemImpl) -> bool {
 
     to_str(ii1) == to_str(ii2)
 }
+
+fn impl_item_attrs(item: &mut ImplItem) -> Option<&mut Vec<Attribute>> {
+    match item {
+        ImplItem::Const(x) => Some(&mut x.attrs),
+        ImplItem::Fn(x) => Some(&mut x.attrs),
+        ImplItem::Type(x) => Some(&mut x.attrs),
+        _ => None,
+    }
+}
@@ -0,0 +1,237 @@
+//! The transformations applied to the modules, and the order they run in.
+//!
+//! The built-in passes run in the order they are registered in `Registry::default`.
+//! The config file can reorder (`passes`) or disable (`disable_passes`) them,
+//! and downstream builds can register passes of their own.
+
+use crate::prelude::*;
+use crate::{argmatch, auto_derive, auto_obj_bound, index, merge_impls, typerec};
+
+/// A transformation of the modules of specr code.
+pub trait Pass {
+    /// The name of this pass, as used in the config file.
+    fn name(&self) -> &str;
+
+    /// The names of the passes which have to run before this one.
+    fn dependencies(&self) -> &[&str] { &[] }
+
+    /// The names of the passes which have to run before this one if they are enabled, but which may also be disabled.
+    fn after(&self) -> &[&str] { &[] }
+
+    /// Transforms the modules, as configured by `config`. Problems are reported to `diags`.
+    fn run(&self, mods: Vec<Module>, config: &Config, diags: &mut Diagnostics) -> Vec<Module>;
+}
+
+/// The passes which are available.
+pub struct Registry {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Default for Registry {
+    /// The built-in passes, in their default order.
+    fn default() -> Registry {
+        let mut r = Registry { passes: Vec::new() };
+        r.register(Argmatch);
+        r.register(Typerec);
+        r.register(ModulePass { name: "merge_impls", f: merge_impls::merge });
+        r.register(ModulePass { name: "auto_derive", f: auto_derive::auto_derive });
+        r.register(ModulePass { name: "index", f: index::index });
+        r.register(ModulePass { name: "auto_obj_bound", f: auto_obj_bound::auto_obj_bound });
+
+        r
+    }
+}
+
+impl Registry {
+    /// Adds `pass`. Unless the config file says otherwise, it runs after all previously registered passes.
+    ///
+    /// Panics if a pass with the same name is registered already.
+    pub fn register(&mut self, pass: impl Pass + 'static) {
+        assert!(self.get(pass.name()).is_none(), "pass `{}` is registered twice", pass.name());
+        self.passes.push(Box::new(pass));
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn Pass> {
+        self.passes.iter().find(|p| p.name() == name).map(|p| &**p)
+    }
+
+    /// Returns the passes to run for `config`, in order.
+    pub(crate) fn pipeline(&self, config: &Config) -> std::result::Result<Vec<&dyn Pass>, Diagnostic> {
+        let available = || format!("the available passes are {}", self.passes.iter().map(|p| format!("`{}`", p.name())).collect::<Vec<_>>().join(", "));
+        let lookup = |name: &str, key: &str| self.get(name).ok_or_else(|| {
+            Diagnostic::error(format!("unknown pass `{name}` in `{key}` of the config file")).note(available())
+        });
+
+        let mut pipeline = match &config.passes {
+            Some(names) => names.iter().map(|n| lookup(n, "passes")).collect::<std::result::Result<Vec<_>, _>>()?,
+            None => self.passes.iter().map(|p| &**p).collect(),
+        };
+        for name in &config.disabled_passes {
+            lookup(name, "disable_passes")?;
+            pipeline.retain(|p| p.name() != name);
+        }
+
+        for (i, p) in pipeline.iter().enumerate() {
+            if pipeline[..i].iter().any(|q| q.name() == p.name()) {
+                return Err(Diagnostic::error(format!("pass `{}` is listed twice in `passes` of the config file", p.name())));
+            }
+            for dep in p.dependencies() {
+                if pipeline[..i].iter().any(|q| q.name() == *dep) { continue; }
+
+                let err = if pipeline.iter().any(|q| q.name() == *dep) {
+                    Diagnostic::error(format!("pass `{}` has to run after pass `{dep}`", p.name()))
+                } else {
+                    Diagnostic::error(format!("pass `{}` requires pass `{dep}`, which is not enabled", p.name()))
+                };
+                return Err(err.note("see `passes` and `disable_passes` in the config file"));
+            }
+            for dep in p.after() {
+                if pipeline[i..].iter().any(|q| q.name() == *dep) {
+                    let err = Diagnostic::error(format!("pass `{}` has to run after pass `{dep}`", p.name()));
+                    return Err(err.note("see `passes` in the config file"));
+                }
+            }
+        }
+
+        for name in &config.emit_after {
+            if self.get(name).is_none() {
+                return Err(Diagnostic::error(format!("unknown pass `{name}` in `--emit-after`")).note(available()));
+            }
+            if !pipeline.iter().any(|p| p.name() == name) {
+                return Err(Diagnostic::error(format!("pass `{name}` in `--emit-after` is not enabled")).note("see `passes` and `disable_passes` in the config file"));
+            }
+        }
+
+        Ok(pipeline)
+    }
+}
+
+/// Generates the match blocks of `#[specr::argmatch]` methods.
+struct Argmatch;
+
+impl Pass for Argmatch {
+    fn name(&self) -> &str { "argmatch" }
+
+    fn run(&self, mods: Vec<Module>, _config: &Config, diags: &mut Diagnostics) -> Vec<Module> {
+        argmatch::argmatch(mods, diags)
+    }
+}
+
+/// Wraps recursive enum variants and structs in `GcCow`, after finding them if `auto_indirection` is set.
+struct Typerec;
+
+impl Pass for Typerec {
+    fn name(&self) -> &str { "typerec" }
+
+    // argmatch generates new match blocks, which typerec has to fix up.
+    fn after(&self) -> &[&str] { &["argmatch"] }
+
+    fn run(&self, mods: Vec<Module>, config: &Config, diags: &mut Diagnostics) -> Vec<Module> {
+        typerec::typerec(mods, config.auto_indirection, diags)
+    }
+}
+
+/// A pass transforming each module on its own.
+struct ModulePass {
+    name: &'static str,
+    f: fn(syn::File) -> syn::File,
+}
+
+impl Pass for ModulePass {
+    fn name(&self) -> &str { self.name }
+
+    fn run(&self, mut mods: Vec<Module>, _config: &Config, _diags: &mut Diagnostics) -> Vec<Module> {
+        for m in mods.iter_mut() {
+            let ast = std::mem::replace(&mut m.ast, File { shebang: None, attrs: Vec::new(), items: Vec::new() });
+            m.ast = (self.f)(ast);
+        }
+
+        mods
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a pass which doesn't do anything.
+    struct Nop {
+        name: &'static str,
+        dependencies: &'static [&'static str],
+        after: &'static [&'static str],
+    }
+
+    impl Pass for Nop {
+        fn name(&self) -> &str { self.name }
+        fn dependencies(&self) -> &[&str] { self.dependencies }
+        fn after(&self) -> &[&str] { self.after }
+        fn run(&self, mods: Vec<Module>, _config: &Config, _diags: &mut Diagnostics) -> Vec<Module> { mods }
+    }
+
+    // the built-in passes, and `a`, `b` which requires `a`, and `c` which runs after `b`.
+    fn registry() -> Registry {
+        let mut r = Registry::default();
+        r.register(Nop { name: "a", dependencies: &[], after: &[] });
+        r.register(Nop { name: "b", dependencies: &["a"], after: &[] });
+        r.register(Nop { name: "c", dependencies: &[], after: &["b"] });
+        r
+    }
+
+    // the names of the passes in the pipeline for `passes`, `disabled` and `emit_after`, or its error message.
+    fn pipeline(passes: Option<&[&str]>, disabled: &[&str], emit_after: &[&str]) -> std::result::Result<Vec<String>, String> {
+        let mut config = Config::new("test");
+        config.passes = passes.map(|p| p.iter().map(|x| x.to_string()).collect());
+        config.disabled_passes = disabled.iter().map(|x| x.to_string()).collect();
+        config.emit_after = emit_after.iter().map(|x| x.to_string()).collect();
+        match registry().pipeline(&config) {
+            Ok(p) => Ok(p.iter().map(|p| p.name().to_string()).collect()),
+            Err(e) => Err(e.message),
+        }
+    }
+
+    #[test]
+    fn default_order() {
+        assert_eq!(pipeline(None, &[], &[]).unwrap(), ["argmatch", "typerec", "merge_impls", "auto_derive", "index", "auto_obj_bound", "a", "b", "c"]);
+        assert_eq!(pipeline(None, &["argmatch", "c"], &["typerec"]).unwrap(), ["typerec", "merge_impls", "auto_derive", "index", "auto_obj_bound", "a", "b"]);
+        assert_eq!(pipeline(Some(&["index", "a", "c", "b"]), &["c"], &[]).unwrap(), ["index", "a", "b"]);
+        // `c` only has to run after `b` if `b` is enabled.
+        assert_eq!(pipeline(Some(&["c", "a"]), &[], &[]).unwrap(), ["c", "a"]);
+    }
+
+    #[test]
+    fn unknown_passes() {
+        assert_eq!(pipeline(Some(&["index", "x"]), &[], &[]).unwrap_err(), "unknown pass `x` in `passes` of the config file");
+        assert_eq!(pipeline(None, &["x"], &[]).unwrap_err(), "unknown pass `x` in `disable_passes` of the config file");
+        assert_eq!(pipeline(None, &[], &["x"]).unwrap_err(), "unknown pass `x` in `--emit-after`");
+        assert_eq!(pipeline(None, &["a", "b"], &["a"]).unwrap_err(), "pass `a` in `--emit-after` is not enabled");
+        assert_eq!(pipeline(Some(&["a", "index", "a"]), &[], &[]).unwrap_err(), "pass `a` is listed twice in `passes` of the config file");
+    }
+
+    #[test]
+    fn dependencies() {
+        assert_eq!(pipeline(None, &["a"], &[]).unwrap_err(), "pass `b` requires pass `a`, which is not enabled");
+        assert_eq!(pipeline(Some(&["b", "a"]), &[], &[]).unwrap_err(), "pass `b` has to run after pass `a`");
+        assert_eq!(pipeline(Some(&["c", "a", "b"]), &[], &[]).unwrap_err(), "pass `c` has to run after pass `b`");
+        assert_eq!(pipeline(Some(&["typerec", "argmatch"]), &[], &[]).unwrap_err(), "pass `typerec` has to run after pass `argmatch`");
+    }
+
+    // passes which require each other can't run in any order.
+    #[test]
+    fn cycles() {
+        let mut r = Registry::default();
+        r.register(Nop { name: "x", dependencies: &["y"], after: &[] });
+        r.register(Nop { name: "y", dependencies: &["x"], after: &[] });
+        let mut config = Config::new("test");
+        for passes in [["x", "y"], ["y", "x"]] {
+            config.passes = Some(passes.iter().map(|x| x.to_string()).collect());
+            let err = r.pipeline(&config).err().unwrap();
+            assert_eq!(err.message, format!("pass `{}` has to run after pass `{}`", passes[0], passes[1]));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "pass `index` is registered twice")]
+    fn registered_twice() {
+        registry().register(Nop { name: "index", dependencies: &[], after: &[] });
+    }
+}
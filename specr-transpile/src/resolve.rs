@@ -0,0 +1,431 @@
+//! A lightweight name resolution for paths in specr code.
+//!
+//! This only knows about the items of the specr modules themselves:
+//! paths into other crates like `libspecr` or `std` are not resolved.
+//! Types and values are not told apart, and items within function bodies are ignored,
+//! except for `use` declarations, see `Scope::uses`.
+
+use crate::prelude::*;
+use std::collections::HashMap;
+
+/// What a path resolves to. Items are given by their full path, like `["lang", "Chain"]`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Res {
+    Mod(Vec<String>),
+    Enum(Vec<String>),
+    /// a variant of the given enum.
+    Variant(Vec<String>, String),
+    Struct(Vec<String>),
+    /// any other item, like a function or a trait.
+    Other(Vec<String>),
+}
+
+impl Res {
+    /// The path of `self` as written in Rust, like `lang::Chain::Cons`.
+    pub fn to_path_string(&self) -> String {
+        match self {
+            Res::Mod(p) | Res::Enum(p) | Res::Struct(p) | Res::Other(p) => p.join("::"),
+            Res::Variant(p, v) => format!("{}::{v}", p.join("::")),
+        }
+    }
+}
+
+/// The result of resolving a path.
+pub enum Resolution {
+    Found(Res),
+    /// the path could refer to each of these, for example due to conflicting glob imports.
+    Ambiguous(Vec<Res>),
+    /// the path does not refer to an item of the specr modules, or we could not figure out what it refers to.
+    Unknown,
+}
+
+/// A single import of a `use` declaration.
+#[derive(Clone)]
+pub struct Use {
+    path: Vec<String>,
+    /// the name the import is available as, or `None` for a glob import.
+    name: Option<String>,
+    public: bool,
+}
+
+/// Where a path is resolved.
+#[derive(Clone, Default)]
+pub struct Scope {
+    /// the path of the surrounding module, including inline modules.
+    pub module: Vec<String>,
+    /// what `Self` refers to.
+    pub self_ty: Option<Res>,
+    /// the `use` declarations in the surrounding blocks.
+    pub uses: Vec<Use>,
+}
+
+#[derive(Default)]
+struct ModScope {
+    // the items defined in this module by name, and whether they are public.
+    items: HashMap<String, (Res, bool)>,
+    // the targets of the type aliases in this module.
+    aliases: HashMap<String, Path>,
+    uses: Vec<Use>,
+}
+
+pub struct Resolver {
+    mods: HashMap<Vec<String>, ModScope>,
+    // the variants of each enum.
+    enums: HashMap<Vec<String>, Vec<String>>,
+}
+
+impl Resolver {
+    pub fn new(mods: &[Module]) -> Resolver {
+        let mut r = Resolver { mods: HashMap::new(), enums: HashMap::new() };
+        r.mods.insert(Vec::new(), ModScope::default());
+        for m in mods {
+            let path = m.full_path();
+            // the modules are declared by their parent, see `submodules` in lib.rs.
+            r.mods.entry(m.path.clone()).or_default().items.insert(m.name.clone(), (Res::Mod(path.clone()), true));
+            r.add_items(&path, &m.ast.items);
+        }
+
+        r
+    }
+
+    fn add_items(&mut self, module: &[String], items: &[Item]) {
+        self.mods.entry(module.to_vec()).or_default();
+        for item in items {
+            let item_path = |ident: &Ident| {
+                let mut p = module.to_vec();
+                p.push(ident.to_string());
+                p
+            };
+            let (ident, res, vis) = match item {
+                Item::Enum(e) => {
+                    let variants = e.variants.iter().map(|v| v.ident.to_string()).collect();
+                    self.enums.insert(item_path(&e.ident), variants);
+                    (&e.ident, Res::Enum(item_path(&e.ident)), &e.vis)
+                },
+                Item::Struct(s) => (&s.ident, Res::Struct(item_path(&s.ident)), &s.vis),
+                Item::Mod(m) => {
+                    if let Some((_, items)) = &m.content {
+                        self.add_items(&item_path(&m.ident), items);
+                    }
+                    (&m.ident, Res::Mod(item_path(&m.ident)), &m.vis)
+                },
+                Item::Type(t) => {
+                    if let Type::Path(p) = &*t.ty {
+                        self.mods.get_mut(module).unwrap().aliases.insert(t.ident.to_string(), p.path.clone());
+                    }
+                    (&t.ident, Res::Other(item_path(&t.ident)), &t.vis)
+                },
+                Item::Fn(f) => (&f.sig.ident, Res::Other(item_path(&f.sig.ident)), &f.vis),
+                Item::Const(c) => (&c.ident, Res::Other(item_path(&c.ident)), &c.vis),
+                Item::Static(s) => (&s.ident, Res::Other(item_path(&s.ident)), &s.vis),
+                Item::Trait(t) => (&t.ident, Res::Other(item_path(&t.ident)), &t.vis),
+                Item::Union(u) => (&u.ident, Res::Other(item_path(&u.ident)), &u.vis),
+                Item::Use(u) => {
+                    let uses = uses(u);
+                    self.mods.get_mut(module).unwrap().uses.extend(uses);
+                    continue;
+                },
+                _ => continue,
+            };
+            let public = !matches!(vis, Visibility::Inherited);
+            self.mods.get_mut(module).unwrap().items.insert(ident.to_string(), (res, public));
+        }
+    }
+
+    /// The names of the variants of the enum `e`.
+    pub fn variants(&self, e: &[String]) -> &[String] {
+        &self.enums[e]
+    }
+
+    /// Resolves `path` in `scope`.
+    pub fn resolve(&self, scope: &Scope, path: &Path) -> Resolution {
+        let segs: Vec<String> = path.segments.iter().map(|s| s.ident.to_string()).collect();
+        let mut visited = HashSet::new();
+        let res = if path.leading_colon.is_some() {
+            // `::a` refers to the crate `a`.
+            Vec::new()
+        } else {
+            self.resolve_segs(scope, &segs, &mut visited)
+        };
+
+        match &res[..] {
+            [] => Resolution::Unknown,
+            [res] => Resolution::Found(res.clone()),
+            _ => Resolution::Ambiguous(res),
+        }
+    }
+
+    // returns the candidates `segs` could refer to.
+    fn resolve_segs(&self, scope: &Scope, segs: &[String], visited: &mut HashSet<(Vec<String>, String)>) -> Vec<Res> {
+        let Some((first, rest)) = segs.split_first() else { return Vec::new() };
+        let mut cur = match &**first {
+            "crate" => vec![Res::Mod(Vec::new())],
+            "self" => vec![Res::Mod(scope.module.clone())],
+            "super" if !scope.module.is_empty() => vec![Res::Mod(scope.module[..scope.module.len()-1].to_vec())],
+            "Self" => scope.self_ty.iter().cloned().collect(),
+            _ => {
+                let found = self.lookup_uses(&scope.module, &scope.uses, first, false, visited);
+                if found.is_empty() {
+                    self.lookup(&scope.module, first, false, visited)
+                } else {
+                    found
+                }
+            },
+        };
+
+        for seg in rest {
+            cur = match &cur[..] {
+                [Res::Mod(m)] if seg == "super" && !m.is_empty() => vec![Res::Mod(m[..m.len()-1].to_vec())],
+                [Res::Mod(m)] => self.lookup(m, seg, !scope.module.starts_with(m), visited),
+                [Res::Enum(e)] if self.enums[e].contains(seg) => vec![Res::Variant(e.clone(), seg.clone())],
+                _ => return Vec::new(),
+            };
+        }
+
+        cur
+    }
+
+    // the candidates for the name `name` in `module`.
+    // If `only_pub` is set, only public items and imports are considered, as the module is accessed from outside.
+    fn lookup(&self, module: &[String], name: &str, only_pub: bool, visited: &mut HashSet<(Vec<String>, String)>) -> Vec<Res> {
+        let Some(m) = self.mods.get(module) else { return Vec::new() };
+        if !visited.insert((module.to_vec(), name.to_string())) { return Vec::new(); }
+
+        let mut found = Vec::new();
+        if let Some((res, public)) = m.items.get(name) {
+            if *public || !only_pub {
+                found.push(match m.aliases.get(name) {
+                    Some(alias) => {
+                        let scope = Scope { module: module.to_vec(), ..Scope::default() };
+                        let segs: Vec<String> = alias.segments.iter().map(|s| s.ident.to_string()).collect();
+                        let res = self.resolve_segs(&scope, &segs, visited);
+                        if res.len() != 1 { return res; }
+                        res[0].clone()
+                    },
+                    None => res.clone(),
+                });
+            }
+        }
+        if found.is_empty() {
+            found = self.lookup_uses(module, &m.uses, name, only_pub, visited);
+        }
+
+        visited.remove(&(module.to_vec(), name.to_string()));
+
+        found
+    }
+
+    // the candidates for the name `name` imported by `uses` in `module`.
+    // Explicit imports shadow glob imports.
+    fn lookup_uses(&self, module: &[String], uses: &[Use], name: &str, only_pub: bool, visited: &mut HashSet<(Vec<String>, String)>) -> Vec<Res> {
+        let scope = Scope { module: module.to_vec(), ..Scope::default() };
+        let uses: Vec<&Use> = uses.iter().filter(|u| u.public || !only_pub).collect();
+
+        let mut found = Vec::new();
+        for u in uses.iter().filter(|u| u.name.as_deref() == Some(name)) {
+            found.extend(self.resolve_segs(&scope, &u.path, visited));
+        }
+        if found.is_empty() {
+            for u in uses.iter().filter(|u| u.name.is_none()) {
+                for target in self.resolve_segs(&scope, &u.path, visited) {
+                    found.extend(match target {
+                        Res::Mod(m) => self.lookup(&m, name, !module.starts_with(&m), visited),
+                        Res::Enum(e) if self.enums[&e].iter().any(|v| v == name) => vec![Res::Variant(e, name.to_string())],
+                        _ => Vec::new(),
+                    });
+                }
+            }
+        }
+
+        let mut deduped = Vec::new();
+        for res in found {
+            if !deduped.contains(&res) {
+                deduped.push(res);
+            }
+        }
+
+        deduped
+    }
+}
+
+/// The imports of the `use` declaration `u`.
+pub fn uses(u: &ItemUse) -> Vec<Use> {
+    let mut out = Vec::new();
+    let public = !matches!(u.vis, Visibility::Inherited);
+    flatten(&u.tree, &mut Vec::new(), public, &mut out);
+
+    out
+}
+
+// flattens the use tree `tree` below `prefix`.
+fn flatten(tree: &UseTree, prefix: &mut Vec<String>, public: bool, out: &mut Vec<Use>) {
+    match tree {
+        UseTree::Path(p) => {
+            prefix.push(p.ident.to_string());
+            flatten(&p.tree, prefix, public, out);
+            prefix.pop();
+        },
+        // `use a::{self}` imports `a`.
+        UseTree::Name(n) if n.ident == "self" => {
+            if let Some(last) = prefix.last() {
+                out.push(Use { path: prefix.clone(), name: Some(last.clone()), public });
+            }
+        },
+        UseTree::Name(n) => {
+            let mut path = prefix.clone();
+            path.push(n.ident.to_string());
+            out.push(Use { path, name: Some(n.ident.to_string()), public });
+        },
+        UseTree::Rename(r) => {
+            let mut path = prefix.clone();
+            path.push(r.ident.to_string());
+            out.push(Use { path, name: Some(r.rename.to_string()), public });
+        },
+        UseTree::Glob(_) => out.push(Use { path: prefix.clone(), name: None, public }),
+        UseTree::Group(g) => {
+            for t in &g.items {
+                flatten(t, prefix, public, out);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LANG: &str = "
+        pub enum Chain { Cons, Nil }
+        pub struct S;
+        struct Private;
+        pub type Alias = Chain;
+        fn f() {}
+        pub mod inner {
+            pub struct T;
+            use super::Chain as C2;
+            pub use super::S as Reexport;
+        }
+    ";
+
+    const EXT: &str = "
+        use crate::lang::*;
+        use crate::lang::Chain as C;
+        use crate::lang::Chain::*;
+        use crate::lang::inner::{self, T};
+    ";
+
+    // `lang`, `ext`, the nested module `lang::sub`, and `amb` which imports two structs `Dup` with globs.
+    fn resolver() -> Resolver {
+        let module = |name: &str, path: &[&str], code: &str| Module {
+            name: name.to_string(),
+            path: path.iter().map(|x| x.to_string()).collect(),
+            ast: parse_str(code).unwrap(),
+        };
+        Resolver::new(&[
+            module("lang", &[], &format!("{LANG} pub struct Dup;")),
+            module("ext", &[], &format!("{EXT} pub struct Dup;")),
+            module("sub", &["lang"], "use super::S as SS; pub struct U;"),
+            module("amb", &[], "use crate::lang::*; use crate::ext::*;"),
+            module("explicit", &[], "use crate::lang::*; use crate::ext::*; use crate::ext::Dup;"),
+        ])
+    }
+
+    // resolves `path` in `scope`, and compares the result with `expected`.
+    fn check_in(r: &Resolver, scope: &Scope, path: &str, expected: &str) {
+        let res = match r.resolve(scope, &parse_str(path).unwrap()) {
+            Resolution::Found(res) => {
+                let kind = match res {
+                    Res::Mod(_) => "mod",
+                    Res::Enum(_) => "enum",
+                    Res::Variant(..) => "variant",
+                    Res::Struct(_) => "struct",
+                    Res::Other(_) => "other",
+                };
+                format!("{kind} {}", res.to_path_string())
+            },
+            Resolution::Ambiguous(_) => "ambiguous".to_string(),
+            Resolution::Unknown => "unknown".to_string(),
+        };
+        assert_eq!(res, expected, "resolving `{path}` in `{}`", scope.module.join("::"));
+    }
+
+    fn check(r: &Resolver, module: &str, path: &str, expected: &str) {
+        let scope = Scope { module: module.split("::").map(String::from).collect(), ..Scope::default() };
+        check_in(r, &scope, path, expected);
+    }
+
+    #[test]
+    fn items() {
+        let r = resolver();
+        check(&r, "lang", "Chain", "enum lang::Chain");
+        check(&r, "lang", "Chain::Nil", "variant lang::Chain::Nil");
+        check(&r, "lang", "Chain::Other", "unknown");
+        check(&r, "lang", "Private", "struct lang::Private");
+        check(&r, "lang", "f", "other lang::f");
+        check(&r, "lang", "Alias::Cons", "variant lang::Chain::Cons");
+        // items of other crates are not resolved.
+        check(&r, "lang", "Vec", "unknown");
+        check(&r, "lang", "::std::vec::Vec", "unknown");
+    }
+
+    #[test]
+    fn paths() {
+        let r = resolver();
+        check(&r, "ext", "crate::lang::S", "struct lang::S");
+        check(&r, "lang", "self::S", "struct lang::S");
+        check(&r, "lang::inner", "super::S", "struct lang::S");
+        check(&r, "lang::inner", "super::super::ext::Dup", "struct ext::Dup");
+        // private items can only be reached from within their module.
+        check(&r, "ext", "crate::lang::Private", "unknown");
+        check(&r, "lang::inner", "super::Private", "struct lang::Private");
+    }
+
+    #[test]
+    fn imports() {
+        let r = resolver();
+        // globs only import public items.
+        check(&r, "ext", "S", "struct lang::S");
+        check(&r, "ext", "Private", "unknown");
+        check(&r, "ext", "f", "unknown");
+        // renames, enum globs and `self` in groups.
+        check(&r, "ext", "C::Cons", "variant lang::Chain::Cons");
+        check(&r, "ext", "Nil", "variant lang::Chain::Nil");
+        check(&r, "ext", "inner::T", "struct lang::inner::T");
+        check(&r, "ext", "T", "struct lang::inner::T");
+        // only `pub use`s can be reached from outside.
+        check(&r, "ext", "crate::lang::inner::Reexport", "struct lang::S");
+        check(&r, "ext", "crate::lang::inner::C2", "unknown");
+        check(&r, "lang::inner", "C2::Nil", "variant lang::Chain::Nil");
+    }
+
+    #[test]
+    fn nested_modules() {
+        let r = resolver();
+        check(&r, "lang", "sub::U", "struct lang::sub::U");
+        check(&r, "ext", "crate::lang::sub::U", "struct lang::sub::U");
+        check(&r, "lang::sub", "SS", "struct lang::S");
+        check(&r, "lang::sub", "super::Private", "struct lang::Private");
+        check(&r, "lang::sub", "self::U", "struct lang::sub::U");
+    }
+
+    #[test]
+    fn ambiguity() {
+        let r = resolver();
+        check(&r, "amb", "Dup", "ambiguous");
+        // explicit imports shadow globs, and the items of the module shadow imports.
+        check(&r, "explicit", "Dup", "struct ext::Dup");
+        check(&r, "ext", "Dup", "struct ext::Dup");
+    }
+
+    #[test]
+    fn scopes() {
+        let r = resolver();
+        let scope = Scope { module: vec!["ext".to_string()], self_ty: Some(Res::Enum(vec!["lang".to_string(), "Chain".to_string()])), uses: Vec::new() };
+        check_in(&r, &scope, "Self::Cons", "variant lang::Chain::Cons");
+
+        // the `use` declarations of blocks shadow those of the module.
+        let u: ItemUse = parse_quote! { use crate::lang::inner::Reexport as T; };
+        let scope = Scope { module: vec!["ext".to_string()], self_ty: None, uses: uses(&u) };
+        check_in(&r, &scope, "T", "struct lang::S");
+    }
+}
@@ -1,100 +1,637 @@
 /// This module gets the source code of MiniRust.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsString;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+
+use proc_macro2::Span;
+use syn::spanned::Spanned;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_quote, Attribute, ImplItem, Item, LitStr, Signature, Token, TraitItem, Visibility};
+
+use crate::config::Config;
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::markdown::{self, Fenced};
+use crate::source_map::{self, Block, Location};
 
 pub struct Module {
     pub name: String,
+    /// the names of the modules enclosing this one, empty for top-level modules.
+    /// Only nested modules (see `Config::nested_modules`) have a non-empty path.
+    pub path: Vec<String>,
     pub ast: syn::File,
 }
 
-/// looks for subdirs in the directory `folder`, and converts them to a module.
-pub fn fetch(folder: &Path) -> Vec<Module> {
-    let mut mods = Vec::new();
+impl Module {
+    /// the names of the enclosing modules, followed by the name of this module.
+    pub fn full_path(&self) -> Vec<String> {
+        let mut path = self.path.clone();
+        path.push(self.name.clone());
 
-    for d in fs::read_dir(folder).unwrap() {
-        let d = d.unwrap();
-        let ty = d.file_type().unwrap();
-        if ty.is_dir() {
-            let name = d.file_name().into_string().unwrap();
-
-            // exclude ".git" from the module candidates.
-            if name == ".git" { continue; }
+        path
+    }
 
-            if let Some(m) = mk_mod(folder.to_string_lossy().as_ref(), &*name) {
-                mods.push(m);
-            }
+    /// the path of the generated file of this module, relative to the generated crate.
+    pub fn file(&self) -> String {
+        let mut file = String::from("src");
+        for p in &self.path {
+            file.push('/');
+            file.push_str(p);
         }
+
+        format!("{file}/{}.rs", self.name)
     }
+}
 
-    // move prelude to the beginning to get macros to work.
-    let i = mods.iter().position(|x| x.name == "prelude").unwrap();
-    mods.swap(0, i);
+/// Caches parsed modules, so that modules whose .md files didn't change are not parsed again.
+/// Only the modules of the last run are kept.
+#[derive(Default)]
+pub struct Cache {
+    // maps a module path like `lang/types` to the hash of its .md files, the parse result and the diagnostics found while parsing.
+    mods: HashMap<String, (u64, Option<syn::File>, Diagnostics)>,
+}
 
-    mods
+// The order in which modules and .md files are read.
+// Paths listed in `order` (relative to `input`) come first in the given order,
+// all other directory entries follow sorted by name.
+struct Order {
+    order: Vec<PathBuf>,
 }
 
-// returns None if the module doesn't contain any source code.
-// TODO use Rusts Path API for this.
-fn mk_mod(basename: &str, modname: &str) -> Option<Module> {
-    let mut code = String::new();
-    let dirname = PathBuf::from(format!("{basename}/{modname}"));
+// the sort key of a path, see `Order::key`.
+type Key = Vec<(usize, OsString)>;
+
+impl Order {
+    // the sort key of the path `rel`, relative to `input`.
+    // Sorting by this key places the contents of a directory where the directory itself is in the order.
+    fn key(&self, rel: &Path) -> Key {
+        let mut prefix = PathBuf::new();
+        rel.components().map(|c| {
+            prefix.push(c);
+            let rank = self.order.iter().position(|o| *o == prefix).unwrap_or(self.order.len());
+
+            (rank, c.as_os_str().to_os_string())
+        }).collect()
+    }
+}
 
-    let mut dirs = vec![dirname];
+/// Reads all .md files within the directory `input`.
+/// Returns their paths relative to `input`, and their content.
+pub fn read_input(input: &Path, diags: &mut Diagnostics) -> Vec<(PathBuf, String)> {
+    let mut files = Vec::new();
+    let mut dirs = vec![input.to_path_buf()];
     while let Some(dir) = dirs.pop() {
-        for f in fs::read_dir(&dir).unwrap() {
-            let f = f.unwrap();
-            let ty = f.file_type().unwrap();
+        let entries = match fs::read_dir(&dir).and_then(|it| it.collect::<Result<Vec<_>, _>>()) {
+            Ok(entries) => entries,
+            Err(e) => {
+                diags.emit(Diagnostic::error(format!("could not read directory `{}`: {e}", dir.display())));
+                continue;
+            },
+        };
+
+        for f in entries {
+            let path = f.path();
+            let Some(name) = file_name(&f, diags) else { continue };
+            // unlike `path.is_dir()`, this doesn't follow symlinks, which could form a cycle.
+            let ty = match f.file_type() {
+                Ok(ty) => ty,
+                Err(e) => {
+                    diags.emit(Diagnostic::error(format!("could not read `{}`: {e}", path.display())));
+                    continue;
+                },
+            };
             if ty.is_dir() {
-                dirs.push(f.path());
+                // exclude ".git" from the module candidates.
+                if name != ".git" {
+                    dirs.push(path);
+                }
                 continue;
             }
-            if !ty.is_file() { continue; }
+            if !ty.is_file() || !name.ends_with(".md") { continue; }
+
+            match fs::read_to_string(&path) {
+                Ok(content) => files.push((path.strip_prefix(input).unwrap().to_path_buf(), content)),
+                Err(e) => diags.emit(Diagnostic::error(format!("could not read `{}`: {e}", path.display()))),
+            }
+        }
+    }
+
+    files
+}
+
+/// Converts the .md files `files` (with paths relative to `input`) to modules.
+/// Each subdir of `input` becomes a module, consisting of all .md files within it.
+/// With `nested_modules`, their subdirs become nested modules instead, following their parent module.
+/// `order` lists paths of modules and .md files relative to `input`, that should come first, in that order.
+pub fn fetch(config: &Config, mut files: Vec<(PathBuf, String)>, cache: &mut Cache, diags: &mut Diagnostics) -> Vec<Module> {
+    let input = &config.input_path();
+
+    // `Path` comparison ignores trailing slashes and `.` components.
+    let order = Order { order: config.order.iter().map(PathBuf::from).collect() };
+    for o in &order.order {
+        if !files.iter().any(|(f, _)| f.starts_with(o)) {
+            diags.emit(Diagnostic::warning(format!("`{}` from the `order` in the config file does not exist in `{}`", o.display(), input.display())));
+        }
+    }
+    let has_prelude = files.iter().any(|(f, _)| f.starts_with("prelude"));
+
+    // the .md files of each module directory, by the sort key of that directory.
+    let mut dirs: BTreeMap<Key, (PathBuf, Vec<(PathBuf, String)>)> = BTreeMap::new();
+    files.sort_by_cached_key(|(f, _)| order.key(f));
+    for (f, content) in files {
+        // files directly within `input` don't belong to any module.
+        let Some(parent) = f.parent().filter(|p| *p != Path::new("")) else { continue };
+        let dir = if config.nested_modules { parent.to_path_buf() } else { parent.iter().take(1).collect() };
+        let path_of_file = input.join(&f).components().filter(|c| *c != Component::CurDir).collect();
+        dirs.entry(order.key(&dir)).or_insert_with(|| (dir, Vec::new())).1.push((path_of_file, content));
+    }
+
+    // modules without own .md files may still be needed to declare their nested modules.
+    let ancestors: Vec<PathBuf> = dirs.values().flat_map(|(dir, _)| dir.ancestors().skip(1).map(Path::to_path_buf)).collect();
+    for a in ancestors {
+        if a != Path::new("") {
+            dirs.entry(order.key(&a)).or_insert_with(|| (a, Vec::new()));
+        }
+    }
+
+    let mut mods: Vec<(Vec<String>, Option<syn::File>)> = Vec::new();
+    for (dir, files) in dirs.into_values() {
+        let Some(path) = module_path(&dir, input, diags) else { continue };
+        let ast = mk_mod(&path, &files, cache, diags);
+        mods.push((path, ast));
+    }
+    // modules which were renamed or removed since the last run are dropped from the cache,
+    // as `source_map::retain` drops their code from the source map.
+    let keys: HashSet<String> = mods.iter().map(|(path, _)| path.join("/")).collect();
+    cache.mods.retain(|key, _| keys.contains(key));
+    // Modules which don't contain any source code, or could not be parsed, are left out;
+    // unless they have nested modules, which have to be declared in them.
+    let needed: Vec<bool> = mods.iter().map(|(path, ast)| {
+        ast.is_some() || mods.iter().any(|(p, a)| a.is_some() && p.starts_with(path))
+    }).collect();
+    let mut needed = needed.into_iter();
+    mods.retain(|_| needed.next().unwrap());
+
+    let mut mods: Vec<Module> = mods.into_iter().map(|(mut path, ast)| {
+        let name = path.pop().unwrap();
+        let ast = ast.unwrap_or_else(|| syn::File { shebang: None, attrs: Vec::new(), items: Vec::new() });
+
+        Module { name, path, ast }
+    }).collect();
+
+    // move prelude (and its nested modules) to the beginning to get macros to work.
+    match mods.iter().position(|x| x.name == "prelude" && x.path.is_empty()) {
+        Some(i) => {
+            let n = 1 + mods[i+1..].iter().take_while(|x| !x.path.is_empty()).count();
+            let prelude: Vec<Module> = mods.drain(i..i+n).collect();
+            mods.splice(0..0, prelude);
+        },
+        // if the prelude exists but failed to parse, we already reported that.
+        None if has_prelude => {},
+        None => {
+            let err = Diagnostic::error(format!("no `prelude` module found in `{}`", input.display()))
+                .note("specr requires a `prelude` folder containing .md files; it is placed first so that its macros are available in all other modules");
+            diags.emit(err);
+        },
+    }
+
+    mods
+}
+
+// returns the names of the modules for the directory `dir`, relative to `input`.
+fn module_path(dir: &Path, input: &Path, diags: &mut Diagnostics) -> Option<Vec<String>> {
+    let path: Vec<String> = dir.iter().map(|c| c.to_string_lossy().to_string()).collect();
+    let name = path.last()?;
+    if syn::parse_str::<syn::Ident>(name).is_err() {
+        let err = Diagnostic::error(format!("directory name `{name}` is not a valid module name"))
+            .note(format!("in `{}`", input.join(dir).display()));
+        diags.emit(err);
+        return None;
+    }
+
+    Some(path)
+}
+
+// The code of a module, concatenated from fenced code blocks.
+#[derive(Default)]
+struct Code {
+    code: String,
+    // the number of lines in `code`.
+    lines: usize,
+    blocks: Vec<Block>,
+    // the prose before each block.
+    docs: Vec<String>,
+}
+
+impl Code {
+    fn push(&mut self, file: &Path, index: usize, fenced: &Fenced) {
+        let bcode = &fenced.code;
+        self.blocks.push(Block {
+            file: file.to_path_buf(),
+            index,
+            md_line: fenced.fence_line + 1,
+            indent: fenced.indent,
+            code_line: self.lines + 1,
+            lines: bcode.lines().count(),
+        });
+        self.docs.push(fenced.prose.clone());
+        self.code.push_str(bcode);
+        self.code.push_str("\n\n");
+        self.lines += bcode.matches('\n').count() + 2;
+    }
+
+    fn parse(&self) -> Result<syn::File, Diagnostic> {
+        let tokens = self.code.parse::<proc_macro2::TokenStream>().map_err(|e| {
+            let mut err = Diagnostic::error(format!("{e}"));
+
+            // We cannot locate lexer errors, so we look for the first block failing on its own.
+            let block = self.blocks.iter().find(|b| {
+                let bcode = self.code.lines().skip(b.code_line - 1).take(b.lines).collect::<Vec<_>>().join("\n");
+                bcode.parse::<proc_macro2::TokenStream>().is_err()
+            });
+            if let Some(b) = block {
+                let loc = Location { file: b.file.clone(), line: b.md_line, column: b.indent + 1, block: b.index };
+                let snippet = self.code.lines().nth(b.code_line - 1).map(String::from);
+                err = err.at_location(loc, snippet).note("this code block contains unbalanced delimiters");
+            }
+
+            err
+        })?;
+        source_map::register(&tokens, &self.code, self.blocks.clone());
 
-            let name = f.file_name().into_string().unwrap();
-            if !name.ends_with(".md") { continue; }
+        syn::parse2::<syn::File>(tokens).map_err(|e| Diagnostic::error(format!("{e}")).at(e.span()))
+    }
 
-            let fcode = fs::read_to_string(f.path()).unwrap();
-            let fcode = filter_specr_lang(&*fcode);
-            code.push_str(&*fcode);
+    // attaches the prose before each block as `///` documentation to the first item of that block,
+    // and `intro` as `//!` documentation to the module.
+    fn document(&self, ast: &mut syn::File, intro: &str) {
+        for (b, doc) in self.blocks.iter().zip(&self.docs) {
+            if doc.is_empty() { continue; }
+            let first = ast.items.iter_mut().find(|item| {
+                let line = item.span().start().line;
+                b.code_line <= line && line < b.code_line + b.lines
+            });
+            let Some(attrs) = first.and_then(item_attrs) else { continue };
+            attrs.splice(0..0, doc_attrs(doc, false));
         }
+
+        ast.attrs.splice(0..0, doc_attrs(intro, true));
+    }
+}
+
+// returns the attributes of `item`, if it can be documented.
+fn item_attrs(item: &mut Item) -> Option<&mut Vec<Attribute>> {
+    let attrs = match item {
+        Item::Const(x) => &mut x.attrs,
+        Item::Enum(x) => &mut x.attrs,
+        Item::ExternCrate(x) => &mut x.attrs,
+        Item::Fn(x) => &mut x.attrs,
+        Item::ForeignMod(x) => &mut x.attrs,
+        Item::Impl(x) => &mut x.attrs,
+        // documentation on macro invocations is unused, only `macro_rules!` can be documented.
+        Item::Macro(x) if x.ident.is_some() => &mut x.attrs,
+        Item::Mod(x) => &mut x.attrs,
+        Item::Static(x) => &mut x.attrs,
+        Item::Struct(x) => &mut x.attrs,
+        Item::Trait(x) => &mut x.attrs,
+        Item::TraitAlias(x) => &mut x.attrs,
+        Item::Type(x) => &mut x.attrs,
+        Item::Union(x) => &mut x.attrs,
+        Item::Use(x) => &mut x.attrs,
+        _ => return None,
+    };
+
+    Some(attrs)
+}
+
+// converts `doc` to `#[doc]` attributes, one per line, or to `#![doc]` attributes if `inner` is set.
+fn doc_attrs(doc: &str, inner: bool) -> Vec<Attribute> {
+    if doc.is_empty() { return Vec::new(); }
+
+    doc.lines().map(|l| {
+        // prettyplease prints `#[doc = " x"]` as `/// x`.
+        let l = if l.is_empty() { String::new() } else { format!(" {l}") };
+        let lit = LitStr::new(&l, Span::call_site());
+        if inner {
+            parse_quote!(#![doc = #lit])
+        } else {
+            parse_quote!(#[doc = #lit])
+        }
+    }).collect()
+}
+
+// parses the module `path` from `files`, unless it is found in `cache`.
+// returns None if the files don't contain any source code, or could not be parsed.
+fn mk_mod(path: &[String], files: &[(PathBuf, String)], cache: &mut Cache, diags: &mut Diagnostics) -> Option<syn::File> {
+    let mut hasher = DefaultHasher::new();
+    files.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let key = path.join("/");
+    let (_, ast, mod_diags) = match cache.mods.get(&key) {
+        Some(entry) if entry.0 == hash => entry,
+        _ => {
+            let mut mod_diags = Diagnostics::default();
+            let ast = parse_mod(files, &mut mod_diags);
+            cache.mods.insert(key.clone(), (hash, ast, mod_diags));
+            &cache.mods[&key]
+        },
+    };
+
+    for d in mod_diags.iter() {
+        diags.emit(d.clone());
     }
 
-    if code.is_empty() { return None; }
+    ast.clone()
+}
+
+// returns None if the files don't contain any source code, or could not be parsed.
+fn parse_mod(files: &[(PathBuf, String)], diags: &mut Diagnostics) -> Option<syn::File> {
+    let mut code = Code::default();
+    // the code of each file on its own, so that parse errors can be reported for every file.
+    let mut file_codes = Vec::new();
+    // the text of the first .md file before its first code block documents the module.
+    let mut intro = String::new();
+
+    for (i, (path, fcode)) in files.iter().enumerate() {
+        let mut fenced = filter_specr_lang(fcode, path, diags);
+        if i == 0 {
+            intro = markdown::intro(fcode);
+            // the prose before the first code block is part of the intro already.
+            let first = markdown::fenced_blocks(fcode).first().map(|b| b.fence_line);
+            if let Some(b) = fenced.first_mut().filter(|b| Some(b.fence_line) == first) {
+                b.prose.clear();
+            }
+        }
 
-    let ast = syn::parse_str::<syn::File>(&*code).unwrap_or_else(|e| {
-        eprintln!("parse error:");
-        let start = e.span().start().line;
-        let start = start.checked_sub(2).unwrap_or(0);
-        let end = e.span().end().line + 2;
-        for x in code.lines().skip(start).take(end-start) {
-            eprintln!("{}", x);
+        let mut fcode_only = Code::default();
+        for (index, fenced) in fenced.iter().enumerate() {
+            code.push(path, index + 1, fenced);
+            fcode_only.push(path, index + 1, fenced);
         }
-        panic!("{}", &e)
+        file_codes.push(fcode_only);
+    }
+
+    if code.code.is_empty() { return None; }
+
+    match code.parse() {
+        Ok(mut ast) => {
+            declarations(&mut ast.items, diags);
+            code.document(&mut ast, &intro);
+            Some(ast)
+        },
+        Err(err) => {
+            // report the first error of every file; or the error of the whole module,
+            // if the files parse fine on their own.
+            let file_errs: Vec<Diagnostic> = file_codes.iter().filter_map(|f| f.parse().err()).collect();
+            if file_errs.is_empty() {
+                diags.emit(err);
+            }
+            for e in file_errs {
+                diags.emit(e);
+            }
+
+            None
+        },
+    }
+}
+
+// Function declarations without a body like `fn foo(x: T) -> U;` are no valid Rust outside of traits,
+// so syn keeps them as verbatim tokens, which can't be printed.
+// They are converted to functions with an empty body if they have an `argmatch` attribute, as that generates their body.
+// Other verbatim items are reported and removed.
+fn declarations(items: &mut Vec<Item>, diags: &mut Diagnostics) {
+    items.retain_mut(|item| match item {
+        Item::Verbatim(tokens) => match declaration(tokens, diags) {
+            Some(FnDecl { attrs, vis, sig }) => {
+                *item = parse_quote! { #(#attrs)* #vis #sig {} };
+                true
+            },
+            None => false,
+        },
+        Item::Impl(ii) => {
+            ii.items.retain_mut(|item| match item {
+                ImplItem::Verbatim(tokens) => match declaration(tokens, diags) {
+                    Some(FnDecl { attrs, vis, sig }) => {
+                        *item = parse_quote! { #(#attrs)* #vis #sig {} };
+                        true
+                    },
+                    None => false,
+                },
+                _ => true,
+            });
+            true
+        },
+        Item::Trait(it) => {
+            it.items.retain(|item| match item {
+                TraitItem::Verbatim(tokens) => {
+                    unsupported(tokens, diags);
+                    false
+                },
+                _ => true,
+            });
+            true
+        },
+        Item::Mod(m) => {
+            if let Some((_, items)) = &mut m.content {
+                declarations(items, diags);
+            }
+            true
+        },
+        _ => true,
     });
-    Some(Module {
-        name: modname.to_string(),
-        ast
-    })
 }
 
+// parses the verbatim tokens of an item as an `argmatch` declaration, or reports them.
+fn declaration(tokens: &proc_macro2::TokenStream, diags: &mut Diagnostics) -> Option<FnDecl> {
+    let Ok(decl) = syn::parse2::<FnDecl>(tokens.clone()) else {
+        unsupported(tokens, diags);
+        return None;
+    };
+    if !decl.attrs.iter().any(crate::argmatch::is_argmatch_attr) {
+        let err = Diagnostic::error(format!("`fn {}` has no body", decl.sig.ident))
+            .at(decl.sig.ident.span())
+            .note("only functions with a `#[specr::argmatch(..)]` declaration can leave out their body, as it is generated from their cases");
+        diags.emit(err);
+        return None;
+    }
+
+    Some(decl)
+}
+
+fn unsupported(tokens: &proc_macro2::TokenStream, diags: &mut Diagnostics) {
+    let err = Diagnostic::error("specr doesn't support this item")
+        .at(tokens.span())
+        .note("syn parses it, but can't represent it, so it can't be printed");
+    diags.emit(err);
+}
+
+// a function declaration without a body, like `fn foo(x: T) -> U;`.
+struct FnDecl {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    sig: Signature,
+}
+
+impl Parse for FnDecl {
+    fn parse(input: ParseStream) -> syn::Result<FnDecl> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let sig = input.parse()?;
+        input.parse::<Token![;]>()?;
+
+        Ok(FnDecl { attrs, vis, sig })
+    }
+}
+
+fn file_name(entry: &fs::DirEntry, diags: &mut Diagnostics) -> Option<String> {
+    let name = entry.file_name().into_string().ok();
+    if name.is_none() {
+        diags.emit(Diagnostic::error(format!("file name `{}` is not valid UTF-8", entry.path().display())));
+    }
+
+    name
+}
 
-// this filters out the code blocks ```rust <code> ```
+// this filters out the code blocks ```rust <code> ```, see the README for the accepted info strings.
 // it will ignore ```rust,ignore <code> ``` blocks
-fn filter_specr_lang(mut s: &str) -> String {
-    const OFFSET1: usize = "\n```rust\n".len();
-    const OFFSET2: usize = "\n```\n".len();
+fn filter_specr_lang(s: &str, file: &Path, diags: &mut Diagnostics) -> Vec<Fenced> {
+    let mut out = Vec::new();
+    for b in markdown::fenced_blocks(s) {
+        let loc = Location { file: file.to_path_buf(), line: b.fence_line, column: b.indent + 1, block: out.len() + 1 };
+        let snippet = s.split('\n').nth(b.fence_line - 1).map(|l| l.trim_end().to_string());
+        let at = |d: Diagnostic| d.at_location(loc.clone(), snippet.clone());
+
+        let info = b.info.split_whitespace().next().unwrap_or("");
+        let mut parts = info.split(',');
+        let lang = parts.next().unwrap();
+        let attrs: Vec<&str> = parts.collect();
+
+        if lang != "rust" {
+            if lang.eq_ignore_ascii_case("rust") || lang.eq_ignore_ascii_case("rs") {
+                let warn = Diagnostic::warning(format!("code block in language `{lang}` is not read as specr code"))
+                    .note("specr code blocks are marked with ```rust");
+                diags.emit(at(warn));
+            }
+            continue;
+        }
+        if attrs.contains(&"ignore") { continue; }
+        for a in attrs {
+            let warn = Diagnostic::warning(format!("unknown code block attribute `{a}`"))
+                .note("the only supported attribute is `ignore`; this block is read as specr code");
+            diags.emit(at(warn));
+        }
+
+        if !b.closed {
+            let err = Diagnostic::error("unclosed code block")
+                .note("a code block has to be closed by a fence like the one opening it");
+            diags.emit(at(err));
+            continue;
+        }
 
-    let mut out = String::new();
-    // note that this find(_) pattern doesn't match "```rust,ignore" due to the final newline.
-    while let Some(i) = s.find("\n```rust\n") {
-        s = &s[i+OFFSET1..];
-        if let Some(j) = s.find("\n```\n") {
-            out.push_str(&s[..j]);
-            out.push_str("\n\n");
-            s = &s[j+OFFSET2..];
-        } else { panic!("unclosed code segment!"); }
+        out.push(b);
     }
 
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sorts `paths` by their key for `order`.
+    fn sorted(order: &[&str], paths: &[&str]) -> Vec<String> {
+        let order = Order { order: order.iter().map(PathBuf::from).collect() };
+        let mut paths: Vec<&str> = paths.to_vec();
+        paths.sort_by_cached_key(|p| order.key(Path::new(p)));
+        paths.into_iter().map(String::from).collect()
+    }
+
+    // the modules `fetch` makes of the .md files `files` like `lang/x.md`, each defining a function named after the file.
+    // Each module is given by its path and its functions, like `lang: x y`.
+    fn fetch_files(config: &Config, files: &[&str]) -> (Vec<String>, Vec<String>) {
+        let files = files.iter().map(|f| {
+            let name = Path::new(f).file_stem().unwrap().to_string_lossy().to_string();
+            (PathBuf::from(f), format!("```rust\nfn {name}() {{}}\n```\n"))
+        }).collect();
+        let mut diags = Diagnostics::default();
+        let mods = fetch(config, files, &mut Cache::default(), &mut diags);
+
+        let mods = mods.iter().map(|m| {
+            let fns: Vec<String> = m.ast.items.iter().filter_map(|item| match item {
+                Item::Fn(f) => Some(f.sig.ident.to_string()),
+                _ => None,
+            }).collect();
+            format!("{}: {}", m.full_path().join("::"), fns.join(" "))
+        }).collect();
+        let messages = diags.iter().map(|d| d.message.clone()).collect();
+
+        (mods, messages)
+    }
+
+    #[test]
+    fn order_key() {
+        let paths = ["lang/b.md", "prelude/p.md", "lang/a.md", "other/x.md", "lang/sub/c.md"];
+        assert_eq!(sorted(&[], &paths), ["lang/a.md", "lang/b.md", "lang/sub/c.md", "other/x.md", "prelude/p.md"]);
+        // listed paths come first, and the files of a directory stay where the directory is.
+        assert_eq!(sorted(&["other", "lang/b.md"], &paths), ["other/x.md", "lang/b.md", "lang/a.md", "lang/sub/c.md", "prelude/p.md"]);
+        assert_eq!(sorted(&["lang/sub", "lang"], &paths), ["lang/sub/c.md", "lang/a.md", "lang/b.md", "other/x.md", "prelude/p.md"]);
+        // `Path` comparison ignores trailing slashes.
+        assert_eq!(sorted(&["other/"], &paths)[0], "other/x.md");
+    }
+
+    #[test]
+    fn fetch_order() {
+        let files = ["other/x.md", "lang/b.md", "prelude/p.md", "lang/a.md", "top.md"];
+        let mut config = Config::new("test");
+        assert_eq!(fetch_files(&config, &files), (vec!["prelude: p".to_string(), "lang: a b".to_string(), "other: x".to_string()], Vec::new()));
+
+        // the prelude stays first.
+        config.order = vec!["other".to_string(), "lang/b.md".to_string(), "prelude".to_string()];
+        assert_eq!(fetch_files(&config, &files).0, ["prelude: p", "other: x", "lang: b a"]);
+
+        config.order = vec!["missing".to_string()];
+        assert_eq!(fetch_files(&config, &files).1, ["`missing` from the `order` in the config file does not exist in `.`"]);
+        assert_eq!(fetch_files(&Config::new("test"), &["lang/a.md"]).1, ["no `prelude` module found in `.`"]);
+    }
+
+    #[test]
+    fn nested_modules() {
+        let files = ["lang/a.md", "lang/types/b.md", "lang/types/deep/c.md", "other/x/y.md", "prelude/p.md", "prelude/m/q.md"];
+        let mut config = Config::new("test");
+        assert_eq!(fetch_files(&config, &files).0, ["prelude: q p", "lang: a b c", "other: y"]);
+
+        config.nested_modules = true;
+        // `other` has no files of its own, but has to declare `x`; the nested modules of the prelude move along with it.
+        let (mods, messages) = fetch_files(&config, &files);
+        assert_eq!(mods, ["prelude: p", "prelude::m: q", "lang: a", "lang::types: b", "lang::types::deep: c", "other: ", "other::x: y"]);
+        assert_eq!(messages, Vec::<String>::new());
+
+        let mods = fetch(&config, files.iter().map(|f| (PathBuf::from(f), "```rust\nfn f() {}\n```".to_string())).collect(), &mut Cache::default(), &mut Diagnostics::default());
+        let paths: Vec<String> = mods.iter().map(Module::file).collect();
+        assert_eq!(paths, ["src/prelude.rs", "src/prelude/m.rs", "src/lang.rs", "src/lang/types.rs", "src/lang/types/deep.rs", "src/other.rs", "src/other/x.rs"]);
+
+        assert_eq!(fetch_files(&config, &["prelude/p.md", "lang/my-types/b.md"]).1, ["directory name `my-types` is not a valid module name"]);
+    }
+
+    // the generated files declare the nested modules.
+    #[test]
+    fn module_tree() {
+        let mut config = Config::new("test");
+        config.nested_modules = true;
+        let inputs = ["prelude/p.md", "lang/types/b.md"].iter()
+            .map(|f| (PathBuf::from(f), "```rust\npub fn f() {}\n```".to_string()))
+            .collect();
+        let t = crate::transpile(&config, &crate::Registry::default(), inputs);
+        assert!(t.diagnostics.iter().next().is_none());
+
+        let file = |path: &str| &t.files.iter().find(|(p, _)| p == path).expect("no such file").1;
+        assert!(file("src/lib.rs").contains("pub mod lang;"));
+        assert!(!file("src/lib.rs").contains("pub mod types;"));
+        assert!(file("src/lang.rs").contains("pub mod types;"));
+        assert!(file("src/lang/types.rs").contains("pub fn f()"));
+    }
+}
@@ -0,0 +1,394 @@
+//! Maps the generated Rust code back to the .md files it originates from.
+//!
+//! Each module is parsed from a concatenation of fenced code blocks.
+//! `proc_macro2` assigns every parsed string its own (thread-local) source file,
+//! so we remember for each such file which block of which .md file ended up at which line.
+//! Any span surviving the transformations can then be traced back to the markdown.
+//!
+//! We also remember the comments of each module, so that they can be put back
+//! next to the tokens they were next to in the input.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Write};
+use std::path::{Path as FsPath, PathBuf};
+use std::rc::Rc;
+
+use proc_macro2::Delimiter;
+use syn::spanned::Spanned;
+
+use crate::prelude::*;
+use crate::comments::{self, Comment};
+use crate::source::Module;
+
+/// A fenced code block, as it was placed into the code of a module.
+#[derive(Clone)]
+pub struct Block {
+    /// the .md file containing this block.
+    pub file: PathBuf,
+    /// the number of this block within its .md file, starting at 1.
+    pub index: usize,
+    /// the .md line the code of this block starts at.
+    pub md_line: usize,
+    /// the indentation that was removed from each line of code, in columns.
+    pub indent: usize,
+    /// the line of the module code this block starts at.
+    pub code_line: usize,
+    /// the number of lines of code in this block.
+    pub lines: usize,
+}
+
+/// A position within a .md file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Location {
+    pub file: PathBuf,
+    /// 1-based line.
+    pub line: usize,
+    /// 1-based column.
+    pub column: usize,
+    /// the number of the fenced code block within `file`, starting at 1.
+    pub block: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // show paths relative to the working directory, like rustc does.
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let file = self.file.strip_prefix(&cwd).unwrap_or(&self.file);
+        write!(f, "{}:{}:{}", file.display(), self.line, self.column)
+    }
+}
+
+// a parsed module, as far as the source map is concerned.
+struct Source {
+    code: Rc<str>,
+    blocks: Rc<[Block]>,
+    comments: Rc<[Comment]>,
+    // the start of every token, sorted.
+    tokens: Rc<[(usize, usize)]>,
+}
+
+thread_local! {
+    // maps the source file of a parsed module (as given by `Span::file`) to its code and blocks.
+    static SOURCES: RefCell<HashMap<String, Source>> = RefCell::new(HashMap::new());
+}
+
+/// Registers the blocks a module's code was concatenated from.
+/// `tokens` has to be the result of parsing `code`.
+pub fn register(tokens: &TokenStream, code: &str, blocks: Vec<Block>) {
+    let Some(tt) = tokens.clone().into_iter().next() else { return };
+    let key = tt.span().file();
+    let mut starts: Vec<(usize, usize)> = leaves(tokens.clone()).iter().map(|l| pos(l.span)).collect();
+    starts.sort();
+    let source = Source {
+        code: code.into(),
+        blocks: blocks.into(),
+        comments: comments::lex(code).into(),
+        tokens: starts.into(),
+    };
+    SOURCES.with(|s| s.borrow_mut().insert(key, source));
+}
+
+/// Forgets the registered modules which are not used by `mods`,
+/// like the ones parsed by earlier runs, whose .md files have changed since.
+pub fn retain(mods: &[Module]) {
+    let used: HashSet<String> = mods.iter()
+        .flat_map(|m| leaves(m.ast.to_token_stream()))
+        .map(|l| l.span.file())
+        .collect();
+    SOURCES.with(|s| s.borrow_mut().retain(|file, _| used.contains(file)));
+}
+
+// the number of registered modules.
+#[cfg(test)]
+pub(crate) fn count() -> usize {
+    SOURCES.with(|s| s.borrow().len())
+}
+
+/// Returns the .md location of `span`, if it stems from a registered module.
+pub fn locate(span: Span) -> Option<Location> {
+    let start = span.start();
+    SOURCES.with(|s| {
+        let s = s.borrow();
+        let blocks = &s.get(&span.file())?.blocks;
+        let block = blocks.iter().find(|b| b.code_line <= start.line && start.line < b.code_line + b.lines)?;
+
+        Some(Location {
+            file: block.file.clone(),
+            line: block.md_line + (start.line - block.code_line),
+            column: start.column + block.indent + 1,
+            block: block.index,
+        })
+    })
+}
+
+/// Returns the source line `span` starts in, if it stems from a registered module.
+pub fn line(span: Span) -> Option<String> {
+    let start = span.start();
+    SOURCES.with(|s| {
+        let s = s.borrow();
+        let code = &s.get(&span.file())?.code;
+        code.lines().nth(start.line.checked_sub(1)?).map(String::from)
+    })
+}
+
+/// The source map of a single generated file.
+pub struct FileMap {
+    /// the mapped tokens of each line, as `(column, location)` pairs sorted by column.
+    lines: Vec<Vec<(usize, Location)>>,
+    /// the (inclusive) line ranges of all top-level items.
+    items: Vec<(usize, usize)>,
+}
+
+/// Prints `ast` using `prettyplease`, and maps the resulting code back to the .md files.
+/// The comments of the input are put back into the code, see `insert_comments`.
+pub fn unparse(ast: &syn::File) -> (String, FileMap) {
+    let code = prettyplease::unparse(ast);
+    let generated: TokenStream = code.parse().expect("prettyplease generated invalid tokens");
+
+    let a = leaves(ast.to_token_stream());
+    let b = leaves(generated.clone());
+    let pairs = align(&a, &b);
+    let (code, line_map) = insert_comments(&code, &a, &b, &pairs);
+
+    let mut lines = vec![Vec::new(); code.lines().count()];
+    for &(i, j) in &pairs {
+        let Some(loc) = locate(a[i].span) else { continue };
+        let start = b[j].span.start();
+        lines[line_map[start.line] - 1].push((start.column + 1, loc));
+    }
+
+    let items = parse2::<syn::File>(generated).expect("prettyplease generated invalid code")
+        .items
+        .iter()
+        .map(|item| {
+            let span = item.span();
+            (line_map[span.start().line], line_map[span.end().line])
+        })
+        .collect();
+
+    (code, FileMap { lines, items })
+}
+
+// Puts the comments of the input back into `code`, the output of prettyplease.
+// `a` are the tokens of the AST, `b` the tokens of `code`, and `pairs` aligns them.
+// A comment on its own line is placed on a line of its own before the token that followed it,
+// any other comment is placed at the end of the line of the token that preceded it.
+// Comments next to tokens that didn't make it into `code` are dropped.
+// Returns the new code, and the new line number of each old (1-based) line.
+fn insert_comments(code: &str, a: &[Leaf], b: &[Leaf], pairs: &[(usize, usize)]) -> (String, Vec<usize>) {
+    let old_lines: Vec<&str> = code.lines().collect();
+
+    // where the tokens of each source ended up, by their start in the source.
+    let mut placed: HashMap<String, HashMap<(usize, usize), usize>> = HashMap::new();
+    for &(i, j) in pairs {
+        let span = a[i].span;
+        placed.entry(span.file()).or_default().entry(pos(span)).or_insert(j);
+    }
+
+    // lines within a multi-line token, like a string literal, must not be touched.
+    let mut inside = HashSet::new();
+    for l in b {
+        let (start, end) = (l.span.start().line, l.span.end().line);
+        inside.extend(start..end);
+    }
+
+    let mut before: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut after: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    SOURCES.with(|s| {
+        let s = s.borrow();
+        for (file, placed) in &placed {
+            let Some(source) = s.get(file) else { continue };
+            for c in source.comments.iter() {
+                // the source tokens right before and after the comment.
+                let k = source.tokens.partition_point(|t| *t < c.start);
+                if c.own_line {
+                    let Some(j) = source.tokens.get(k).and_then(|t| placed.get(t)) else { continue };
+                    let line = b[*j].span.start().line;
+                    if line > 1 && inside.contains(&(line - 1)) { continue; }
+                    let indent = &old_lines[line-1][..old_lines[line-1].len() - old_lines[line-1].trim_start().len()];
+                    let text = reindent(&c.text, c.start.1, indent);
+                    before.entry(line).or_default().push(text);
+                } else {
+                    let Some(j) = k.checked_sub(1).and_then(|k| placed.get(&source.tokens[k])) else { continue };
+                    let line = b[*j].span.end().line;
+                    if inside.contains(&line) { continue; }
+                    after.entry(line).or_default().push(c.text.clone());
+                }
+            }
+        }
+    });
+
+    let mut out = String::new();
+    let mut line_map = vec![0];
+    let mut n = 0;
+    for (l, text) in old_lines.iter().enumerate() {
+        let l = l + 1;
+        for c in before.get(&l).into_iter().flatten() {
+            writeln!(out, "{c}").unwrap();
+            n += c.lines().count();
+        }
+        out.push_str(text);
+        for c in after.get(&l).into_iter().flatten() {
+            write!(out, " {c}").unwrap();
+            n += c.lines().count() - 1;
+        }
+        out.push('\n');
+        n += 1;
+        line_map.push(n);
+    }
+
+    (out, line_map)
+}
+
+// indents `comment`, which started at column `column` in the source, by `indent` instead.
+fn reindent(comment: &str, column: usize, indent: &str) -> String {
+    comment.lines().enumerate().map(|(i, l)| {
+        let l = if i == 0 {
+            l
+        } else {
+            // strip the indentation the source had.
+            let ws = l.chars().take(column).take_while(|c| c.is_whitespace()).count();
+            &l[l.char_indices().nth(ws).map_or(l.len(), |(b, _)| b)..]
+        };
+        format!("{indent}{l}")
+    }).collect::<Vec<_>>().join("\n")
+}
+
+// the (1-based) line and (0-based) column `span` starts at.
+fn pos(span: Span) -> (usize, usize) {
+    let start = span.start();
+
+    (start.line, start.column)
+}
+
+impl FileMap {
+    /// Returns the .md location of the given (1-based) position in the generated file.
+    /// Lines without any mapped token, like those of generated `#[derive]`s,
+    /// are attributed to the nearest mapped line within the same item.
+    pub fn lookup(&self, line: usize, column: usize) -> Option<Location> {
+        let mapped = |l: usize| self.lines.get(l.checked_sub(1)?).filter(|x| !x.is_empty());
+
+        let tokens = match mapped(line) {
+            Some(tokens) => tokens,
+            None => {
+                let &(start, end) = self.items.iter().find(|(s, e)| (*s..=*e).contains(&line))?;
+                ((line+1)..=end).chain((start..line).rev()).find_map(mapped)?
+            },
+        };
+
+        // the last token starting at or before `column`, or else the first one of the line.
+        let (_, loc) = tokens.iter().rev().find(|(c, _)| *c <= column).unwrap_or(&tokens[0]);
+
+        Some(loc.clone())
+    }
+
+    /// Appends the mapping of the generated file `name` to `out`, one mapped token per line:
+    /// `<generated file> <line> <column> <.md file> <line> <column> <block>`, separated by tabs.
+    /// Paths of .md files are relative to `input`.
+    pub fn write(&self, name: &str, input: &FsPath, out: &mut String) {
+        for (l, tokens) in self.lines.iter().enumerate() {
+            for (c, loc) in tokens {
+                let md = loc.file.strip_prefix(input).unwrap_or(&loc.file);
+                writeln!(out, "{name}\t{}\t{c}\t{}\t{}\t{}\t{}", l+1, md.display(), loc.line, loc.column, loc.block).unwrap();
+            }
+        }
+    }
+}
+
+/// Rewrites the `--> file:line:col` locations of a rendered rustc diagnostic to point into the .md files.
+/// `maps` is indexed by the path of the generated file, relative to the generated crate.
+/// The generated location is kept as a secondary `:::` line, as the code snippets still show the generated code.
+pub fn relocate(rendered: &str, maps: &HashMap<String, FileMap>) -> String {
+    let mut out = String::new();
+    for line in rendered.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let loc = trimmed.strip_prefix("--> ").or_else(|| trimmed.strip_prefix("::: "))
+            .and_then(|pos| {
+                let mut it = pos.rsplitn(3, ':');
+                let col = it.next()?.parse().ok()?;
+                let l = it.next()?.parse().ok()?;
+                maps.get(it.next()?)?.lookup(l, col)
+            });
+
+        match loc {
+            Some(loc) => {
+                let arrow = &trimmed[..4];
+                writeln!(out, "{indent}{arrow}{loc}").unwrap();
+                writeln!(out, "{indent}::: {} (generated)", &trimmed[4..]).unwrap();
+            },
+            None => writeln!(out, "{line}").unwrap(),
+        }
+    }
+
+    out
+}
+
+// A token of a flattened `TokenStream`. Delimiters of groups are tokens on their own.
+struct Leaf {
+    text: String,
+    span: Span,
+}
+
+fn leaves(ts: TokenStream) -> Vec<Leaf> {
+    let mut out = Vec::new();
+    flatten(ts, &mut out);
+
+    out
+}
+
+fn flatten(ts: TokenStream, out: &mut Vec<Leaf>) {
+    for tt in ts {
+        match tt {
+            TokenTree::Group(g) => {
+                let (open, close) = match g.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    // invisible groups are not printed, so we only keep their content.
+                    Delimiter::None => ("", ""),
+                };
+                if !open.is_empty() {
+                    out.push(Leaf { text: open.to_string(), span: g.span_open() });
+                }
+                flatten(g.stream(), out);
+                if !close.is_empty() {
+                    out.push(Leaf { text: close.to_string(), span: g.span_close() });
+                }
+            },
+            tt => out.push(Leaf { text: tt.to_string(), span: tt.span() }),
+        }
+    }
+}
+
+// Pairs up equal tokens of `a` and `b`.
+// prettyplease mostly prints the tokens of the AST as they are, but sometimes it adds
+// parentheses or trailing commas. We resynchronize after such differences by skipping
+// the fewest tokens necessary on either side.
+fn align(a: &[Leaf], b: &[Leaf]) -> Vec<(usize, usize)> {
+    const LOOKAHEAD: usize = 16;
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].text == b[j].text {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let (si, sj) = (1..LOOKAHEAD).find_map(|k| {
+            if a.get(i+k).map_or(false, |x| x.text == b[j].text) {
+                Some((k, 0))
+            } else if b.get(j+k).map_or(false, |x| x.text == a[i].text) {
+                Some((0, k))
+            } else { None }
+        }).unwrap_or((1, 1));
+        i += si;
+        j += sj;
+    }
+
+    pairs
+}
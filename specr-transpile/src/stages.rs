@@ -0,0 +1,88 @@
+//! Records the code after each pass, for debugging the passes.
+//! See `--emit-after` and `--emit-all-stages` in the README.
+
+use crate::prelude::*;
+use crate::diff;
+
+/// The directory within the output, which the stages are written to.
+pub const DIR: &str = "specr-stages";
+
+pub struct Stages<'a> {
+    config: &'a Config,
+    // the number of stages so far, the parsed input being stage 0.
+    n: usize,
+    // the code of each module after the last stage, if we needed it.
+    last: Option<Vec<(String, String)>>,
+    /// the files to write, by their path within the output.
+    pub files: Vec<(String, String)>,
+}
+
+impl<'a> Stages<'a> {
+    /// Starts recording, `mods` being the parsed input.
+    pub fn new(config: &'a Config, mods: &[Module]) -> Stages<'a> {
+        let mut stages = Stages { config, n: 0, last: None, files: Vec::new() };
+        if config.emit_all_stages {
+            stages.emit("parsed", mods);
+        }
+
+        stages
+    }
+
+    fn wanted(&self, pass: &str) -> bool {
+        self.config.emit_all_stages || self.config.emit_after.iter().any(|p| p == pass)
+    }
+
+    /// Call this before running `pass` on `mods`.
+    pub fn before(&mut self, pass: &str, mods: &[Module]) {
+        if self.wanted(pass) && self.config.emit_diff && self.last.is_none() {
+            self.last = Some(render(mods));
+        }
+    }
+
+    /// Call this after running `pass`, which resulted in `mods`.
+    pub fn after(&mut self, pass: &str, mods: &[Module]) {
+        self.n += 1;
+        if self.wanted(pass) {
+            self.emit(pass, mods);
+        } else {
+            self.last = None;
+        }
+    }
+
+    fn emit(&mut self, stage: &str, mods: &[Module]) {
+        let name = format!("{DIR}/{:02}-{stage}", self.n);
+        let code = render(mods);
+        for (file, c) in &code {
+            self.files.push((format!("{name}/{file}"), c.clone()));
+        }
+
+        if self.config.emit_diff {
+            if let Some(last) = &self.last {
+                self.files.push((format!("{name}.diff"), diff_all(last, &code)));
+            }
+        }
+        self.last = Some(code);
+    }
+}
+
+// prints each module, by the path of its file relative to `src`.
+fn render(mods: &[Module]) -> Vec<(String, String)> {
+    mods.iter().map(|m| {
+        let file = m.file();
+        let file = file.strip_prefix("src/").unwrap_or(&file).to_string();
+        let (code, _) = crate::source_map::unparse(&m.ast);
+
+        (file, code)
+    }).collect()
+}
+
+// the diff of all modules from `old` to `new`, including modules which are only in one of them.
+fn diff_all(old: &[(String, String)], new: &[(String, String)]) -> String {
+    let get = |files: &[(String, String)], name: &str| files.iter().find(|(f, _)| f == name).map_or(String::new(), |(_, c)| c.clone());
+
+    let mut names: Vec<&String> = old.iter().chain(new).map(|(f, _)| f).collect();
+    names.sort();
+    names.dedup();
+
+    names.iter().map(|n| diff::unified(n, &get(old, n), &get(new, n))).collect()
+}
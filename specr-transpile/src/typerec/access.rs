@@ -0,0 +1,633 @@
+use crate::typerec::*;
+use crate::index::{bind, index_arg, is_assign_op, is_range, strip_parens};
+use crate::resolve;
+use std::collections::HashMap;
+
+/// Fix accesses to struct fields that were `GcCow<_>`-wrapped, given the names `members` of these fields.
+///
+/// We mostly don't know the type of `s` in `s.x`, so every access to a field named like a wrapped one is converted,
+/// and the libspecr traits in `field.rs` take care of unwrapping only the `GcCow`s:
+/// `s.x` ==> `(&s.x).__specr_get_field()`
+/// `s.x = 2` ==> `(&mut s.x).__specr_set_field(2)`
+/// `s.x += 2` and `s.x.y = 2` use `__specr_mutate_field` instead.
+///
+/// Calls which might mutate a field, as they call a method on it or take it as `&mut` argument, use `__specr_mutate_field` too:
+/// `s.x.push(2)` ==> `(&mut s.x).__specr_mutate_field(|x| (*x).push(2))`, if `s` can be mutated,
+/// `f(&mut s.x)` ==> `(&mut s.x).__specr_mutate_field(|x| f(&mut *x))`.
+/// Other mutable borrows of such a field are an error, as they would go to a copy.
+///
+/// Otherwise, borrowing `s.x` or calling a method on it reads a copy of it, which can't be mutated.
+/// That copy would be wrong for a plain field, so there the field is only converted if `s` is known to be a struct with that field wrapped,
+/// like `self` or an annotated variable, and it's an error if we can't tell, and some struct has a plain field of that name.
+pub(in crate::typerec) fn access(mods: &mut [Module], elements: &HashSet<Element>, members: &HashSet<Member>, resolver: &Resolver, diags: &mut Diagnostics) {
+    if members.is_empty() { return; }
+
+    let structs = structs(mods);
+    let mut errors = Vec::new();
+    for m in mods {
+        let scope = Scope { module: m.full_path(), ..Scope::default() };
+        let mut v = Visitor { members, elements, resolver, structs: &structs, scope, locals: Vec::new(), errors: &mut errors };
+        v.visit_file_mut(&mut m.ast);
+    }
+    for err in errors {
+        diags.emit(err);
+    }
+}
+
+/// A struct of the specr modules, with the scope its field types are resolved in.
+struct StructInfo {
+    scope: Scope,
+    fields: Fields,
+}
+
+// the structs of the specr modules by their full path.
+fn structs(mods: &[Module]) -> HashMap<Vec<String>, StructInfo> {
+    fn collect(module: &[String], items: &[Item], structs: &mut HashMap<Vec<String>, StructInfo>) {
+        for item in items {
+            match item {
+                Item::Struct(s) => {
+                    let mut path = module.to_vec();
+                    path.push(s.ident.to_string());
+                    let scope = Scope { module: module.to_vec(), self_ty: Some(Res::Struct(path.clone())), ..Scope::default() };
+                    structs.insert(path, StructInfo { scope, fields: s.fields.clone() });
+                },
+                Item::Mod(m) => {
+                    if let Some((_, items)) = &m.content {
+                        let mut module = module.to_vec();
+                        module.push(m.ident.to_string());
+                        collect(&module, items, structs);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    let mut structs = HashMap::new();
+    for m in mods {
+        collect(&m.full_path(), &m.ast.items, &mut structs);
+    }
+
+    structs
+}
+
+/// A variable in scope.
+struct Var {
+    name: String,
+    /// the struct it has, if we know it.
+    ty: Option<Vec<String>>,
+    /// whether the places through it can be mutated, like for `mut x`, `x: &mut S` or `&mut self`.
+    mutable: bool,
+}
+
+struct Visitor<'a> {
+    members: &'a HashSet<Member>,
+    elements: &'a HashSet<Element>,
+    resolver: &'a Resolver,
+    structs: &'a HashMap<Vec<String>, StructInfo>,
+    /// where the types currently visited are resolved.
+    scope: Scope,
+    /// the variables in scope. Later ones shadow earlier ones.
+    locals: Vec<Var>,
+    /// the errors found so far, without duplicates.
+    errors: &'a mut Vec<Diagnostic>,
+}
+
+impl VisitMut for Visitor<'_> {
+    fn visit_expr_mut(&mut self, node: &mut Expr) {
+        match node {
+            Expr::Assign(a) if self.has_wrapped(&a.left) => {
+                *node = self.assign(&a.left, None, &a.right);
+                return;
+            },
+            Expr::Binary(b) if is_assign_op(&b.op) && self.has_wrapped(&b.left) => {
+                *node = self.assign(&b.left, Some(&b.op), &b.right);
+                return;
+            },
+            Expr::Call(_) | Expr::MethodCall(_) if self.mutated_place(node).is_some() => {
+                *node = self.mutating_call(node);
+                return;
+            },
+            Expr::MethodCall(mc) => {
+                self.visit_place_mut(&mut mc.receiver, true);
+                for arg in &mut mc.args {
+                    self.visit_expr_mut(arg);
+                }
+                return;
+            },
+            Expr::Reference(r) => {
+                if r.mutability.is_some() && self.has_wrapped(&r.expr) {
+                    self.mutable_borrow(r);
+                }
+                self.visit_place_mut(&mut r.expr, false);
+                return;
+            },
+            _ => {},
+        }
+
+        let wrapped = matches!(node, Expr::Field(f) if self.wrapped(f) != Some(false));
+        visit_expr_mut(self, node);
+
+        if wrapped {
+            *node = parse_quote! { (&#node).__specr_get_field() };
+        }
+    }
+
+    // fixup accesses within macro arguments like `assert_eq!(s.x, 2)`.
+    fn visit_macro_mut(&mut self, i: &mut Macro) {
+        visit_macro_args(self, i);
+
+        visit_macro_mut(self, i);
+    }
+
+    // `let s: S = ..` and `let s = S { .. }` make `s` a known struct,
+    // and `let s = &mut ..` and `let s = &..` tell whether the places through `s` can be mutated.
+    fn visit_local_mut(&mut self, i: &mut Local) {
+        let (ty, borrow) = match (&i.pat, &i.init) {
+            (Pat::Ident(PatIdent { subpat: None, .. }), Some(init)) => match &*init.expr {
+                Expr::Reference(r) => (self.ty_of(&init.expr), Some(r.mutability.is_some())),
+                e => (self.ty_of(e), None),
+            },
+            _ => (None, None),
+        };
+        visit_local_mut(self, i);
+        if let Pat::Ident(pi) = &i.pat {
+            let mutable = borrow.unwrap_or(pi.mutability.is_some());
+            self.locals.push(Var { name: pi.ident.to_string(), ty, mutable });
+        }
+    }
+
+    fn visit_pat_type_mut(&mut self, i: &mut PatType) {
+        match &*i.pat {
+            Pat::Ident(pi @ PatIdent { subpat: None, .. }) => {
+                let ty = self.struct_of(&self.scope, &i.ty);
+                let mutable = match &*i.ty {
+                    Type::Reference(r) => r.mutability.is_some(),
+                    _ => pi.mutability.is_some(),
+                };
+                self.locals.push(Var { name: pi.ident.to_string(), ty, mutable });
+            },
+            _ => visit_pat_type_mut(self, i),
+        }
+    }
+
+    fn visit_pat_ident_mut(&mut self, i: &mut PatIdent) {
+        // this includes `ref mut x`.
+        self.locals.push(Var { name: i.ident.to_string(), ty: None, mutable: i.mutability.is_some() });
+        visit_pat_ident_mut(self, i);
+    }
+
+    fn visit_receiver_mut(&mut self, i: &mut Receiver) {
+        let ty = self.struct_of(&self.scope, &i.ty);
+        let mutable = match &*i.ty {
+            Type::Reference(r) => r.mutability.is_some(),
+            _ => i.mutability.is_some(),
+        };
+        self.locals.push(Var { name: "self".to_string(), ty, mutable });
+    }
+
+    // the bindings of functions, closures, blocks and match arms go out of scope at their end.
+    fn visit_item_fn_mut(&mut self, i: &mut ItemFn) {
+        let n = self.locals.len();
+        visit_item_fn_mut(self, i);
+        self.locals.truncate(n);
+    }
+
+    fn visit_impl_item_fn_mut(&mut self, i: &mut ImplItemFn) {
+        let n = self.locals.len();
+        visit_impl_item_fn_mut(self, i);
+        self.locals.truncate(n);
+    }
+
+    fn visit_trait_item_fn_mut(&mut self, i: &mut TraitItemFn) {
+        let n = self.locals.len();
+        visit_trait_item_fn_mut(self, i);
+        self.locals.truncate(n);
+    }
+
+    fn visit_expr_closure_mut(&mut self, i: &mut ExprClosure) {
+        let n = self.locals.len();
+        visit_expr_closure_mut(self, i);
+        self.locals.truncate(n);
+    }
+
+    fn visit_arm_mut(&mut self, i: &mut Arm) {
+        let n = self.locals.len();
+        visit_arm_mut(self, i);
+        self.locals.truncate(n);
+    }
+
+    // The `use` declarations of the block are taken into account when resolving types, like in `fix`.
+    fn visit_block_mut(&mut self, i: &mut Block) {
+        let (n, m) = (self.locals.len(), self.scope.uses.len());
+        for stmt in &i.stmts {
+            if let Stmt::Item(Item::Use(u)) = stmt {
+                self.scope.uses.extend(resolve::uses(u));
+            }
+        }
+        visit_block_mut(self, i);
+        self.locals.truncate(n);
+        self.scope.uses.truncate(m);
+    }
+
+    fn visit_item_mod_mut(&mut self, i: &mut ItemMod) {
+        self.scope.module.push(i.ident.to_string());
+        visit_item_mod_mut(self, i);
+        self.scope.module.pop();
+    }
+
+    fn visit_item_impl_mut(&mut self, i: &mut ItemImpl) {
+        let self_ty = match &*i.self_ty {
+            Type::Path(p) if p.qself.is_none() => match self.resolver.resolve(&self.scope, &p.path) {
+                Resolution::Found(res) => Some(res),
+                _ => None,
+            },
+            _ => None,
+        };
+        let old = std::mem::replace(&mut self.scope.self_ty, self_ty);
+        visit_item_impl_mut(self, i);
+        self.scope.self_ty = old;
+    }
+
+    fn visit_item_trait_mut(&mut self, i: &mut ItemTrait) {
+        let old = self.scope.self_ty.take();
+        visit_item_trait_mut(self, i);
+        self.scope.self_ty = old;
+    }
+}
+
+impl Visitor<'_> {
+    // whether the field access `f` goes to a wrapped field:
+    // `Some(..)` if the field isn't named like a wrapped one, or if we know the struct of `f.base`, and `None` otherwise.
+    fn wrapped(&self, f: &ExprField) -> Option<bool> {
+        if !self.members.contains(&f.member) { return Some(false); }
+        let s = self.ty_of(&f.base)?;
+
+        Some(self.elements.contains(&Element { path: s, idx: element_idx(&f.member) }))
+    }
+
+    // the struct of the specr modules which the expression `e` has, if we can tell.
+    fn ty_of(&self, e: &Expr) -> Option<Vec<String>> {
+        match e {
+            Expr::Path(p) if p.qself.is_none() => {
+                let ident = p.path.get_ident()?.to_string();
+                self.locals.iter().rev().find(|l| l.name == ident)?.ty.clone()
+            },
+            Expr::Struct(s) if s.qself.is_none() => match self.resolver.resolve(&self.scope, &s.path) {
+                Resolution::Found(Res::Struct(s)) => Some(s),
+                _ => None,
+            },
+            Expr::Field(f) => {
+                let s = self.ty_of(&f.base)?;
+                let info = self.structs.get(&s)?;
+                let field = field(&info.fields, &f.member)?;
+                let ty = match self.elements.contains(&Element { path: s, idx: element_idx(&f.member) }) {
+                    // the type was wrapped into a `libspecr::hidden::GcCow<_>`.
+                    true => gc_cow_arg(&field.ty)?,
+                    false => &field.ty,
+                };
+                self.struct_of(&info.scope, ty)
+            },
+            Expr::Paren(p) => self.ty_of(&p.expr),
+            Expr::Reference(r) => self.ty_of(&r.expr),
+            Expr::Unary(ExprUnary { op: UnOp::Deref(_), expr, .. }) => self.ty_of(expr),
+            _ => None,
+        }
+    }
+
+    // the struct of the specr modules which the type `ty` refers to, seeing through references.
+    fn struct_of(&self, scope: &Scope, ty: &Type) -> Option<Vec<String>> {
+        match ty {
+            Type::Reference(r) => self.struct_of(scope, &r.elem),
+            Type::Paren(p) => self.struct_of(scope, &p.elem),
+            Type::Path(p) if p.qself.is_none() => match self.resolver.resolve(scope, &p.path) {
+                Resolution::Found(Res::Struct(s)) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // fixes the place `e`, which is borrowed or which a method is called on (if `receiver`), without mutating it.
+    // A wrapped field can only be read as a copy there, but a plain field has to be left alone.
+    fn visit_place_mut(&mut self, e: &mut Expr, receiver: bool) {
+        let f = match e {
+            Expr::Paren(p) => return self.visit_place_mut(&mut p.expr, receiver),
+            Expr::Field(f) => f,
+            e => return self.visit_expr_mut(e),
+        };
+        let wrapped = match self.wrapped(f) {
+            Some(wrapped) => wrapped,
+            None => {
+                self.check_plain(f);
+                true
+            },
+        };
+        self.visit_expr_mut(&mut f.base);
+
+        // the copy a method is called on is borrowed, so that the method can't mutate it by accident.
+        if wrapped && receiver {
+            *e = parse_quote! { (&(&#f).__specr_get_field()) };
+        } else if wrapped {
+            *e = parse_quote! { (&#f).__specr_get_field() };
+        }
+    }
+
+    // reports an error if some struct has a plain field named like the one of `f`, whose struct is unknown.
+    fn check_plain(&mut self, f: &ExprField) {
+        let mut wrapped = Vec::new();
+        let mut plain = Vec::new();
+        for (path, info) in self.structs {
+            if field(&info.fields, &f.member).is_none() { continue; }
+            let name = format!("`{}`", path.join("::"));
+            match self.elements.contains(&Element { path: path.clone(), idx: element_idx(&f.member) }) {
+                true => wrapped.push(name),
+                false => plain.push(name),
+            }
+        }
+        if plain.is_empty() { return; }
+        wrapped.sort();
+        plain.sort();
+
+        let base = f.base.to_token_stream().to_string().replace(' ', "");
+        let member = f.member.to_token_stream().to_string();
+        let err = Diagnostic::error(format!("can't tell whether `{base}.{member}` is a field with `#[specr::indirection]`"))
+            .at(match &f.member {
+                Member::Named(m) => m.span(),
+                Member::Unnamed(m) => m.span,
+            })
+            .note(format!("`{member}` has an indirection in {}, but not in {}", wrapped.join(", "), plain.join(", ")))
+            .note(format!("specr needs to know the struct of `{base}` where a field is borrowed or a method is called on it; give it a type annotation, like `let x: S = ..`"));
+        if !self.errors.contains(&err) {
+            self.errors.push(err);
+        }
+    }
+
+    // whether the place `e` goes through a wrapped field.
+    fn has_wrapped(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Field(f) => self.wrapped(f) != Some(false) || self.has_wrapped(&f.base),
+            Expr::Index(i) => self.has_wrapped(&i.expr),
+            Expr::Paren(p) => self.has_wrapped(&p.expr),
+            _ => false,
+        }
+    }
+
+    // lowers `place = value`, or `place op= value` if `op` is given, like `index::assign`.
+    fn assign(&mut self, place: &Expr, op: Option<&BinOp>, value: &Expr) -> Expr {
+        let mut value = value.clone();
+        self.visit_expr_mut(&mut value);
+
+        let mut lets = Vec::new();
+        let val = format_ident!("__specr_val");
+        let stmt = match (op, strip_parens(place)) {
+            // `s.x = c` doesn't need to read `s.x`, it just sets it.
+            (None, Expr::Field(f)) if self.wrapped(f) != Some(false) => {
+                let member = &f.member;
+                self.lower(&f.base, &mut lets, &|p| quote! { (&mut (#p).#member).__specr_set_field(#val) })
+            },
+            (None, place) => self.lower(place, &mut lets, &|p| quote! { #p = #val }),
+            (Some(op), place) => self.lower(place, &mut lets, &|p| quote! { #p #op #val }),
+        };
+
+        parse_quote! {
+            {
+                #( #lets )*
+                let #val = #value;
+                #stmt;
+            }
+        }
+    }
+
+    // the place which the call `e` might mutate, and which goes through a wrapped field:
+    // its receiver if it can be mutated, or an argument like `&mut s.x`.
+    // That is `None` for the receiver, and the index of the argument otherwise.
+    fn mutated_place(&self, e: &Expr) -> Option<(Option<usize>, Expr)> {
+        let (receiver, args) = match e {
+            Expr::Call(c) => (None, &c.args),
+            Expr::MethodCall(mc) => (Some(&*mc.receiver), &mc.args),
+            _ => return None,
+        };
+        if let Some(r) = receiver {
+            if self.has_wrapped(r) && self.mutable(r) {
+                return Some((None, r.clone()));
+            }
+        }
+        args.iter().enumerate().find_map(|(i, arg)| match arg {
+            Expr::Reference(ExprReference { mutability: Some(_), expr, .. }) if self.has_wrapped(expr) => Some((Some(i), (**expr).clone())),
+            _ => None,
+        })
+    }
+
+    // lowers the call `e`, which might mutate a wrapped field, see `mutated_place`.
+    // The call is moved into the `__specr_mutate_field` closure of the field;
+    // its other arguments are evaluated into variables first, as the closure may not read the mutated struct.
+    fn mutating_call(&mut self, e: &Expr) -> Expr {
+        let (pos, place) = self.mutated_place(e).unwrap();
+        let mut call = e.clone();
+        let (receiver, args) = match &mut call {
+            Expr::Call(c) => {
+                self.visit_expr_mut(&mut c.func);
+                (None, &mut c.args)
+            },
+            Expr::MethodCall(mc) => (Some(&mut mc.receiver), &mut mc.args),
+            _ => unreachable!(),
+        };
+        if let (Some(r), Some(_)) = (receiver, pos) {
+            self.visit_place_mut(r, true);
+        }
+
+        let mut lets = Vec::new();
+        let mut vals = Vec::new();
+        for (i, arg) in args.iter_mut().enumerate() {
+            if Some(i) == pos { continue; }
+            if let Expr::Reference(r) = arg {
+                if r.mutability.is_some() && self.has_wrapped(&r.expr) {
+                    // only one field can be mutated in the closure.
+                    self.mutable_borrow(r);
+                }
+            }
+            self.visit_expr_mut(arg);
+            let val = format_ident!("__specr_arg{i}");
+            vals.push(quote! { let #val = #arg; });
+            *arg = parse_quote! { #val };
+        }
+
+        let stmt = self.lower(&place, &mut lets, &|p| {
+            let mut call = call.clone();
+            match (&mut call, pos) {
+                (Expr::MethodCall(mc), None) => *mc.receiver = parse_quote! { (#p) },
+                (Expr::Call(ExprCall { args, .. }) | Expr::MethodCall(ExprMethodCall { args, .. }), Some(i)) => args[i] = parse_quote! { &mut #p },
+                _ => unreachable!(),
+            }
+            call.to_token_stream()
+        });
+
+        parse_quote! {
+            {
+                #( #lets )*
+                #( #vals )*
+                #stmt
+            }
+        }
+    }
+
+    // reports the mutable borrow `r` of a place going through a wrapped field, which can't be lowered.
+    fn mutable_borrow(&mut self, r: &ExprReference) {
+        let place = r.expr.to_token_stream().to_string().replace(' ', "");
+        let err = Diagnostic::error(format!("`{place}` can't be borrowed mutably here, as it goes through a field with `#[specr::indirection]`"))
+            .at(r.and_token.span)
+            .note("such a field can only be mutated by assigning to it, by calling a method on it, or by passing it like `f(&mut s.x)` to a call, once per call");
+        if !self.errors.contains(&err) {
+            self.errors.push(err);
+        }
+    }
+
+    // whether the place `e` can be mutated, as far as we can tell:
+    // whether it is based on a variable which is mutable, or is a mutable reference.
+    fn mutable(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Path(p) if p.qself.is_none() => {
+                let Some(ident) = p.path.get_ident() else { return false };
+                self.locals.iter().rev().find(|l| *ident == l.name).map_or(false, |l| l.mutable)
+            },
+            Expr::Field(ExprField { base: e, .. }) | Expr::Index(ExprIndex { expr: e, .. }) | Expr::Paren(ExprParen { expr: e, .. }) => self.mutable(e),
+            Expr::Unary(ExprUnary { op: UnOp::Deref(_), expr, .. }) => self.mutable(expr),
+            _ => false,
+        }
+    }
+
+    // calls `action` with the Rust expression for the place `place`,
+    // wrapping it in `__specr_mutate_field` calls for every wrapped field within the place.
+    // Indices within the place are lowered to `mutate_at` calls, as the index pass can't see through the closures.
+    fn lower(&mut self, place: &Expr, lets: &mut Vec<TokenStream>, action: &dyn Fn(TokenStream) -> TokenStream) -> TokenStream {
+        match strip_parens(place) {
+            Expr::Field(f) if self.wrapped(f) != Some(false) => {
+                let member = &f.member;
+                let field = format_ident!("__specr_field");
+                let inner = action(quote! { *#field });
+                self.lower(&f.base, lets, &|p| quote! { (&mut (#p).#member).__specr_mutate_field(|#field| { #inner }) })
+            },
+            Expr::Field(f) => {
+                let member = &f.member;
+                self.lower(&f.base, lets, &|p| action(quote! { (#p).#member }))
+            },
+            Expr::Index(idx) => {
+                let mutate = if is_range(&idx.index) { format_ident!("mutate_subslice") } else { format_ident!("mutate_at") };
+                let mut index = (*idx.index).clone();
+                self.visit_expr_mut(&mut index);
+                let i = bind(&index_arg(&index), lets);
+                let elem = format_ident!("__specr_elem");
+                let inner = action(quote! { *#elem });
+                self.lower(&idx.expr, lets, &|p| quote! { (#p).#mutate(#i, |#elem| { #inner }) })
+            },
+            place => {
+                let mut place = place.clone();
+                self.visit_expr_mut(&mut place);
+                action(place.to_token_stream())
+            },
+        }
+    }
+}
+
+// the field `m` of a struct with the fields `fields`.
+fn field<'a>(fields: &'a Fields, m: &Member) -> Option<&'a Field> {
+    match m {
+        Member::Named(m) => fields.iter().find(|f| f.ident.as_ref() == Some(m)),
+        Member::Unnamed(m) => fields.iter().filter(|f| f.ident.is_none()).nth(m.index as usize),
+    }
+}
+
+fn element_idx(m: &Member) -> ElementIdx {
+    match m {
+        Member::Named(m) => ElementIdx::Named(m.clone()),
+        Member::Unnamed(m) => ElementIdx::Unnamed(m.index as usize),
+    }
+}
+
+// the `T` of the type `libspecr::hidden::GcCow<T>` of a wrapped field.
+fn gc_cow_arg(ty: &Type) -> Option<&Type> {
+    let Type::Path(p) = ty else { return None };
+    let PathArguments::AngleBracketed(args) = &p.path.segments.last()?.arguments else { return None };
+    match args.args.first()? {
+        GenericArgument::Type(t) => Some(t),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile_rust;
+
+    const HOLDER: &str = "
+        pub struct H {
+            #[specr::indirection]
+            x: List<Int>,
+            n: Int,
+        }
+    ";
+
+    // the code generated for the functions `f`, without whitespace.
+    fn lang(f: &str) -> String {
+        let t = transpile_rust(&[("lang/a.md", &format!("{HOLDER}\n{f}"))]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        t.code("src/lang.rs")
+    }
+
+    fn errors(f: &str) -> Vec<String> {
+        let t = transpile_rust(&[("lang/a.md", &format!("{HOLDER}\n{f}"))]);
+        t.messages().into_iter().map(String::from).collect()
+    }
+
+    fn squash(s: &str) -> String {
+        s.split_whitespace().collect()
+    }
+
+    #[test]
+    fn read() {
+        let code = lang("fn f(h: H) -> Int { h.x.len() + h.n }");
+        assert!(code.contains(&squash("(&(&h.x).__specr_get_field()).len() + h.n")));
+    }
+
+    // the write to a mutably borrowed field has to go to the field, and not to a copy.
+    #[test]
+    fn mutable_borrow() {
+        let code = lang("fn f(mut h: H) { g(&mut h.x, h.n); }");
+        assert!(code.contains(&squash("
+            let __specr_arg1 = h.n;
+            (&mut (h).x).__specr_mutate_field(|__specr_field| { g(&mut *__specr_field, __specr_arg1) })
+        ")));
+        assert!(!code.contains("__specr_get_field"));
+    }
+
+    #[test]
+    fn mutating_method() {
+        let code = lang("impl H { fn f(&mut self) -> Int { self.x.push(self.n); self.x.len() } }");
+        assert!(code.contains(&squash("
+            let __specr_arg0 = self.n;
+            (&mut (self).x).__specr_mutate_field(|__specr_field| { (*__specr_field).push(__specr_arg0) })
+        ")));
+        assert!(code.contains(&squash("(&mut (self).x).__specr_mutate_field(|__specr_field| { (*__specr_field).len() })")));
+        assert!(!code.contains("__specr_get_field"));
+    }
+
+    // methods can only read a copy of fields which can't be mutated, so that rustc rejects mutating methods.
+    #[test]
+    fn immutable_receiver() {
+        let code = lang("fn f(h: &H) { h.x.push(h.n); }");
+        assert!(code.contains(&squash("(&(&h.x).__specr_get_field()).push(h.n)")));
+    }
+
+    #[test]
+    fn plain_field() {
+        let code = lang("impl H { fn f(&mut self) { self.n.add_assign(self.n); let m = &mut self.n; } }");
+        assert!(code.contains(&squash("self.n.add_assign(self.n); let m = &mut self.n;")));
+    }
+
+    #[test]
+    fn unsupported_borrows() {
+        let err = |place: &str| format!("`{place}` can't be borrowed mutably here, as it goes through a field with `#[specr::indirection]`");
+        assert_eq!(errors("fn f(mut h: H) { let x = &mut h.x; }"), [err("h.x")]);
+        assert_eq!(errors("fn f(mut h: H, mut k: H) { g(&mut h.x, &mut k.x); }"), [err("k.x")]);
+    }
+}
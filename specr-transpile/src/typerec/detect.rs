@@ -0,0 +1,408 @@
+use crate::typerec::*;
+use crate::config::AutoIndirection;
+use std::collections::{BTreeMap, HashMap};
+
+/// The generic types which store their arguments behind a pointer, so they don't contribute to the size of a type.
+const INDIRECTIONS: &[&str] = &["List", "Set", "Map", "GcCow", "Box", "Rc", "Arc", "Vec", "VecDeque", "HashMap", "HashSet", "BTreeMap", "BTreeSet", "PhantomData"];
+
+/// Finds the enum variant and struct fields which need an indirection, because their type contains itself.
+/// Depending on `mode`, these fields get a `#[specr::indirection]`, or an error asks to add one.
+///
+/// The structs and enums of all modules make up a graph: each field points to the types it contains by value,
+/// following tuples, arrays and generic arguments. Every cycle of this graph needs an indirection.
+pub(in crate::typerec) fn detect(mods: &mut [Module], resolver: &Resolver, mode: AutoIndirection, diags: &mut Diagnostics) {
+    if mode == AutoIndirection::Off { return; }
+
+    let mut defs = BTreeMap::new();
+    for m in mods.iter() {
+        collect_defs(&m.full_path(), &m.ast.items, &mut defs);
+    }
+    let graph = Graph::new(&defs, resolver);
+
+    let mut cut = HashSet::new();
+    while let Some(cycle) = graph.find_cycle(&cut) {
+        // the indirection goes to the field closing the cycle.
+        let key = cycle.last().unwrap();
+        let (def, i) = key;
+        let f = &defs[def].fields[*i];
+        if mode == AutoIndirection::Report {
+            let owner = match &f.variant {
+                Some(v) => format!("variant `{}::{v}`", def.last().unwrap()),
+                None => format!("struct `{}`", def.last().unwrap()),
+            };
+            diags.emit(Diagnostic::error(format!("recursive type `{}` has infinite size", def.join("::")))
+                .at(f.span)
+                .note(format!("the recursion {} needs an indirection", describe(&cycle)))
+                .note(format!("add `#[specr::indirection]` to the field `{}` of the {owner}", f.name))
+                .note("or set `auto_indirection = \"insert\"` in the config file"));
+        }
+        cut.insert(key.clone());
+    }
+
+    if mode == AutoIndirection::Insert {
+        for m in mods {
+            insert_attrs(&m.full_path(), &mut m.ast.items, &cut);
+        }
+    }
+}
+
+// a struct or an enum.
+struct Def {
+    module: Vec<String>,
+    // the names of the type parameters.
+    params: Vec<Ident>,
+    // the fields of the struct, or of all variants of the enum in order.
+    fields: Vec<DefField>,
+}
+
+struct DefField {
+    // the variant of the enum this field belongs to, or `None` for struct fields.
+    variant: Option<Ident>,
+    // the name or the position of the field.
+    name: String,
+    ty: Type,
+    span: Span,
+    // whether the field has a `#[specr::indirection]` already.
+    indirection: bool,
+}
+
+// a field, given by the path of its struct or enum, and its index in `Def::fields`.
+type FieldKey = (Vec<String>, usize);
+
+fn collect_defs(module: &[String], items: &[Item], defs: &mut BTreeMap<Vec<String>, Def>) {
+    for item in items {
+        let (ident, generics, fields) = match item {
+            Item::Struct(s) => (&s.ident, &s.generics, def_fields(None, &s.fields)),
+            Item::Enum(e) => {
+                let fields = e.variants.iter().flat_map(|v| def_fields(Some(&v.ident), &v.fields)).collect();
+                (&e.ident, &e.generics, fields)
+            },
+            Item::Mod(m) => {
+                if let Some((_, items)) = &m.content {
+                    let mut path = module.to_vec();
+                    path.push(m.ident.to_string());
+                    collect_defs(&path, items, defs);
+                }
+                continue;
+            },
+            _ => continue,
+        };
+        let mut path = module.to_vec();
+        path.push(ident.to_string());
+        let params = generics.type_params().map(|p| p.ident.clone()).collect();
+        defs.insert(path, Def { module: module.to_vec(), params, fields });
+    }
+}
+
+fn def_fields(variant: Option<&Ident>, fields: &Fields) -> Vec<DefField> {
+    fields.iter().enumerate().map(|(i, f)| DefField {
+        variant: variant.cloned(),
+        name: f.ident.as_ref().map_or_else(|| i.to_string(), |x| x.to_string()),
+        ty: f.ty.clone(),
+        span: f.ident.as_ref().map_or_else(|| spanned::Spanned::span(&f.ty), |x| x.span()),
+        indirection: f.attrs.iter().any(wrap::is_indirection_attr),
+    }).collect()
+}
+
+// what a type contains by value.
+enum Contained {
+    Def(Vec<String>),
+    // a type parameter of the struct or enum the type is part of.
+    Param(Ident),
+}
+
+struct Graph<'a> {
+    defs: &'a BTreeMap<Vec<String>, Def>,
+    resolver: &'a Resolver,
+    // for each struct and enum, which of its type parameters it contains by value.
+    by_value: HashMap<Vec<String>, Vec<bool>>,
+    // for each field, the structs and enums it contains by value.
+    edges: HashMap<FieldKey, Vec<Vec<String>>>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(defs: &'a BTreeMap<Vec<String>, Def>, resolver: &'a Resolver) -> Graph<'a> {
+        let by_value = defs.iter().map(|(path, def)| (path.clone(), vec![false; def.params.len()])).collect();
+        let mut g = Graph { defs, resolver, by_value, edges: HashMap::new() };
+
+        // a parameter can be contained through the parameter of another type, so this has to reach a fixpoint.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (path, def) in defs {
+                for f in def.fields.iter().filter(|f| !f.indirection) {
+                    let mut out = Vec::new();
+                    g.contained(def, &f.ty, &mut out);
+                    for c in out {
+                        let Contained::Param(p) = c else { continue };
+                        let i = def.params.iter().position(|q| *q == p).unwrap();
+                        let b = &mut g.by_value.get_mut(path).unwrap()[i];
+                        changed |= !*b;
+                        *b = true;
+                    }
+                }
+            }
+        }
+
+        for (path, def) in defs {
+            for (i, f) in def.fields.iter().enumerate().filter(|(_, f)| !f.indirection) {
+                let mut out = Vec::new();
+                g.contained(def, &f.ty, &mut out);
+                let targets = out.into_iter().filter_map(|c| match c {
+                    Contained::Def(d) => Some(d),
+                    Contained::Param(_) => None,
+                }).collect();
+                g.edges.insert((path.clone(), i), targets);
+            }
+        }
+
+        g
+    }
+
+    // pushes what the type `ty` of a field of `def` contains by value to `out`.
+    fn contained(&self, def: &Def, ty: &Type, out: &mut Vec<Contained>) {
+        match ty {
+            Type::Path(p) if p.qself.is_none() => {
+                if let Some(ident) = p.path.get_ident() {
+                    if def.params.contains(ident) {
+                        out.push(Contained::Param(ident.clone()));
+                        return;
+                    }
+                }
+
+                let scope = Scope { module: def.module.clone(), ..Scope::default() };
+                let last = p.path.segments.last().unwrap();
+                let args: Vec<&Type> = match &last.arguments {
+                    PathArguments::AngleBracketed(a) => a.args.iter().filter_map(|a| match a {
+                        GenericArgument::Type(t) => Some(t),
+                        _ => None,
+                    }).collect(),
+                    _ => Vec::new(),
+                };
+                match self.resolver.resolve(&scope, &p.path) {
+                    Resolution::Found(Res::Enum(path) | Res::Struct(path)) if self.defs.contains_key(&path) => {
+                        for (arg, by_value) in args.iter().zip(&self.by_value[&path]) {
+                            if *by_value {
+                                self.contained(def, arg, out);
+                            }
+                        }
+                        out.push(Contained::Def(path));
+                    },
+                    // types of other crates, like `Option<T>`, are assumed to contain their arguments.
+                    _ if !INDIRECTIONS.contains(&&*last.ident.to_string()) => {
+                        for arg in args {
+                            self.contained(def, arg, out);
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            Type::Tuple(t) => {
+                for t in &t.elems {
+                    self.contained(def, t, out);
+                }
+            },
+            Type::Array(a) => self.contained(def, &a.elem, out),
+            Type::Paren(p) => self.contained(def, &p.elem, out),
+            Type::Group(g) => self.contained(def, &g.elem, out),
+            // references, pointers, function pointers and trait objects are indirections.
+            _ => {},
+        }
+    }
+
+    // returns the fields of a cycle which doesn't go through fields in `cut`, if there is one.
+    // The structs and enums are searched in the order of their paths, so the result is deterministic.
+    fn find_cycle(&self, cut: &HashSet<FieldKey>) -> Option<Vec<FieldKey>> {
+        let mut done = HashSet::new();
+        for path in self.defs.keys() {
+            let mut stack = Vec::new();
+            if let Some(cycle) = self.dfs(path, cut, &mut stack, &mut done) {
+                return Some(cycle);
+            }
+        }
+
+        None
+    }
+
+    // `stack` holds the fields leading to `path`.
+    fn dfs(&self, path: &Vec<String>, cut: &HashSet<FieldKey>, stack: &mut Vec<FieldKey>, done: &mut HashSet<Vec<String>>) -> Option<Vec<FieldKey>> {
+        if done.contains(path) { return None; }
+
+        for i in 0..self.defs[path].fields.len() {
+            let key = (path.clone(), i);
+            if cut.contains(&key) { continue; }
+            let Some(targets) = self.edges.get(&key) else { continue };
+            stack.push(key);
+            for t in targets {
+                // `t` is on the stack, so we found a cycle back to it.
+                if let Some(start) = stack.iter().position(|(p, _)| p == t) {
+                    return Some(stack[start..].to_vec());
+                }
+                if let Some(cycle) = self.dfs(t, cut, stack, done) {
+                    return Some(cycle);
+                }
+            }
+            stack.pop();
+        }
+        done.insert(path.clone());
+
+        None
+    }
+}
+
+// describes the `cycle` like "`A` -> `B` -> `A`".
+fn describe(cycle: &[FieldKey]) -> String {
+    cycle.iter()
+        .map(|(path, _)| format!("`{}`", path.join("::")))
+        .chain(std::iter::once(format!("`{}`", cycle[0].0.join("::"))))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+// adds `#[specr::indirection]` to the fields in `cut`, in the same order `collect_defs` visits them.
+fn insert_attrs(module: &[String], items: &mut [Item], cut: &HashSet<FieldKey>) {
+    for item in items {
+        let (ident, fields): (_, Vec<&mut Field>) = match item {
+            Item::Enum(e) => (&e.ident, e.variants.iter_mut().flat_map(|v| v.fields.iter_mut()).collect()),
+            Item::Struct(s) => (&s.ident, s.fields.iter_mut().collect()),
+            Item::Mod(m) => {
+                if let Some((_, items)) = &mut m.content {
+                    let mut path = module.to_vec();
+                    path.push(m.ident.to_string());
+                    insert_attrs(&path, items, cut);
+                }
+                continue;
+            },
+            _ => continue,
+        };
+        let mut path = module.to_vec();
+        path.push(ident.to_string());
+        for (i, f) in fields.into_iter().enumerate() {
+            if cut.contains(&(path.clone(), i)) {
+                f.attrs.push(parse_quote! { #[specr::indirection] });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modules(mods: &[(&str, &str)]) -> Vec<Module> {
+        mods.iter().map(|(name, code)| Module { name: name.to_string(), path: Vec::new(), ast: parse_str(code).unwrap() }).collect()
+    }
+
+    // the fields which get an indirection inserted, like `lang::E::Node.0`.
+    fn inserted(mods: &[(&str, &str)]) -> Vec<String> {
+        let mut mods = modules(mods);
+        let resolver = Resolver::new(&mods);
+        let mut before = Vec::new();
+        for m in &mods {
+            marked(&m.name, &m.ast.items, &mut before);
+        }
+        let mut diags = Diagnostics::default();
+        detect(&mut mods, &resolver, AutoIndirection::Insert, &mut diags);
+        assert!(diags.iter().next().is_none());
+
+        let mut after = Vec::new();
+        for m in &mods {
+            marked(&m.name, &m.ast.items, &mut after);
+        }
+        after.retain(|f| !before.contains(f));
+
+        after
+    }
+
+    // pushes the fields of `items` with a `#[specr::indirection]` to `out`.
+    fn marked(module: &str, items: &[Item], out: &mut Vec<String>) {
+        for item in items {
+            let fields: Vec<(String, &Field)> = match item {
+                Item::Struct(s) => s.fields.iter().map(|f| (s.ident.to_string(), f)).collect(),
+                Item::Enum(e) => e.variants.iter().flat_map(|v| v.fields.iter().map(|f| (format!("{}::{}", e.ident, v.ident), f))).collect(),
+                Item::Mod(m) => {
+                    if let Some((_, items)) = &m.content {
+                        marked(&format!("{module}::{}", m.ident), items, out);
+                    }
+                    continue;
+                },
+                _ => continue,
+            };
+            // the fields of enums are numbered within their variant.
+            let mut variant = String::new();
+            let mut i = 0;
+            for (owner, f) in fields {
+                if owner != variant {
+                    variant = owner.clone();
+                    i = 0;
+                }
+                if f.attrs.iter().any(wrap::is_indirection_attr) {
+                    let name = f.ident.as_ref().map_or_else(|| i.to_string(), |x| x.to_string());
+                    out.push(format!("{module}::{owner}.{name}"));
+                }
+                i += 1;
+            }
+        }
+    }
+
+    // checks that the recursion of `code` in `lang` is broken by indirections on the fields `expected`.
+    fn check(code: &str, expected: &[&str]) {
+        assert_eq!(inserted(&[("lang", code)]), expected, "in `{code}`");
+    }
+
+    #[test]
+    fn recursion() {
+        check("pub struct A { n: Int, next: Option<A> }", &["lang::A.next"]);
+        check("pub enum E { Leaf, Node(Int, (Int, [E; 2])) }", &["lang::E::Node.1"]);
+        check("pub struct A { b: B } pub struct B { a: A, other: A }", &["lang::B.a", "lang::B.other"]);
+        // `mod` items are part of the graph as well.
+        check("pub struct A { b: inner::B } mod inner { pub struct B { a: super::A } }", &["lang::inner::B.a"]);
+    }
+
+    #[test]
+    fn no_recursion() {
+        check("pub struct A { next: List<A> }", &[]);
+        check("pub struct A<'a> { next: &'a A<'a>, f: fn(A) -> A }", &[]);
+        check("pub struct A { #[specr::indirection] next: Option<A> }", &[]);
+        // `Option` of another crate is just assumed to contain its argument.
+        check("pub struct A { next: Option<B> } pub struct B;", &[]);
+    }
+
+    #[test]
+    fn generics() {
+        check("pub struct W<T> { t: T } pub struct A { w: W<A> }", &["lang::A.w"]);
+        check("pub struct W<T> { t: List<T> } pub struct A { w: W<A> }", &[]);
+        // the parameter is contained through another struct.
+        check("pub struct V<T> { t: T } pub struct W<T> { v: V<T> } pub struct A { w: W<A> }", &["lang::A.w"]);
+    }
+
+    // the cycle goes through a struct of another module, named by an import.
+    #[test]
+    fn across_modules() {
+        let lang = "pub enum E { Leaf, Node(crate::ext::F) }";
+        let ext = "use crate::lang::E as Tree; pub struct F { n: Int, t: Tree }";
+        assert_eq!(inserted(&[("lang", lang), ("ext", ext)]), ["lang::E::Node.0"]);
+        // without the import, `E` doesn't refer to the enum.
+        let ext = "pub struct F { n: Int, t: E }";
+        assert_eq!(inserted(&[("lang", lang), ("ext", ext)]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn report() {
+        let mut mods = modules(&[("lang", "pub struct A { b: crate::ext::B }"), ("ext", "pub struct B { a: crate::lang::A }")]);
+        let resolver = Resolver::new(&mods);
+        let mut diags = Diagnostics::default();
+        detect(&mut mods, &resolver, AutoIndirection::Report, &mut diags);
+        let d: Vec<&Diagnostic> = diags.iter().collect();
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].message, "recursive type `lang::A` has infinite size");
+        assert_eq!(d[0].notes[0], "the recursion `ext::B` -> `lang::A` -> `ext::B` needs an indirection");
+        assert_eq!(d[0].notes[1], "add `#[specr::indirection]` to the field `b` of the struct `A`");
+        // reporting doesn't change the code.
+        assert!(mods.iter().all(|m| !m.ast.to_token_stream().to_string().contains("indirection")));
+
+        let mut diags = Diagnostics::default();
+        detect(&mut mods, &resolver, AutoIndirection::Off, &mut diags);
+        assert!(diags.iter().next().is_none());
+    }
+}
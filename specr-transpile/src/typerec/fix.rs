@@ -1,33 +1,43 @@
 use crate::typerec::*;
+use crate::resolve;
 
-/// Fix constructing and matching of enum variants that were `GcCow<_>`-wrapped.
-pub(in crate::typerec) fn fix(mods: &mut [Module], elements: &HashSet<VariantElement>) {
+/// Fix constructing and matching of enum variants and structs that were `GcCow<_>`-wrapped.
+/// Errors about paths which can't be resolved precisely are reported to `diags`.
+pub(in crate::typerec) fn fix(mods: &mut [Module], elements: &HashSet<Element>, arities: &HashMap<Vec<String>, usize>, resolver: &Resolver, diags: &mut Diagnostics) {
+    let mut cx = Cx { elements, arities, resolver, scope: Scope::default(), errors: Vec::new() };
     for m in mods {
-        Visitor { elements }.visit_file_mut(&mut m.ast);
+        cx.scope = Scope { module: m.full_path(), ..Scope::default() };
+        let mut v = Visitor { cx, fresh: 0 };
+        v.visit_file_mut(&mut m.ast);
+        cx = v.cx;
+    }
+    for err in cx.errors {
+        diags.emit(err);
     }
 }
 
 struct Visitor<'a> {
-    elements: &'a HashSet<VariantElement>,
+    cx: Cx<'a>,
+    // the number of fresh bindings introduced so far, see `pat_idents`.
+    fresh: usize,
 }
 
 impl VisitMut for Visitor<'_> {
-    // fixup named enum variant construction:
+    // fixup named enum variant and struct construction:
     // `Variant { x: 2 }` ==> `Variant { x: libspecr::hidden::GcCow::new(2) }`
     fn visit_expr_struct_mut(&mut self, i: &mut ExprStruct) {
-        for e in self.elements {
-            let ElementIdx::Named(name) = &e.idx else { continue };
-
-            let p = extract_variant(&i.path);
-            if p == e.variant {
-                for f in &mut i.fields {
-                    let Member::Named(m) = &f.member else { continue };
-                    if m == name {
-                        // this solves the case `Variant { x }`.
-                        f.colon_token = Some(Default::default());
-
-                        wrap_expr(&mut f.expr);
-                    }
+        if let Some(path) = self.cx.wrapped(&i.path) {
+            for f in &mut i.fields {
+                let idx = match &f.member {
+                    Member::Named(m) => ElementIdx::Named(m.clone()),
+                    Member::Unnamed(m) => ElementIdx::Unnamed(m.index as usize),
+                };
+                let e = Element { path: path.clone(), idx };
+                if self.cx.elements.contains(&e) {
+                    // this solves the case `Variant { x }`.
+                    f.colon_token = Some(Default::default());
+
+                    wrap_expr(&mut f.expr);
                 }
             }
         }
@@ -35,38 +45,474 @@ impl VisitMut for Visitor<'_> {
         visit_expr_struct_mut(self, i);
     }
 
-    // fixup unnamed enum variant construction:
+    // fixup unnamed enum variant and tuple struct construction:
     // `Some(2)` ==> `Some(libspecr::hidden::GcCow::new(2))`
     fn visit_expr_call_mut(&mut self, i: &mut ExprCall) {
-        for e in self.elements {
-            let ElementIdx::Unnamed(idx) = &e.idx else { continue };
-            let Expr::Path(p) = &*i.func else { continue };
-            let var = extract_variant(&p.path);
-
-            if e.variant == var {
-                let Some(arg_ref) = i.args.iter_mut().nth(*idx) else { continue };
-                wrap_expr(arg_ref);
+        if let Expr::Path(p) = &*i.func {
+            if let Some(path) = self.cx.wrapped(&p.path) {
+                for (idx, arg) in i.args.iter_mut().enumerate() {
+                    let e = Element { path: path.clone(), idx: ElementIdx::Unnamed(idx) };
+                    if self.cx.elements.contains(&e) {
+                        wrap_expr(arg);
+                    }
+                }
             }
         }
 
         visit_expr_call_mut(self, i);
     }
 
+    // split or-patterns of match arms, whose alternatives bind wrapped elements differently:
+    // `Foo::A { x } | Foo::B(x) => body` ==> `Foo::A { x } => body, Foo::B(x) => body`
+    // Otherwise `x` would need to be extracted in one alternative, but not in the other.
+    fn visit_expr_match_mut(&mut self, i: &mut ExprMatch) {
+        let mut arms = Vec::new();
+        for arm in std::mem::take(&mut i.arms) {
+            match &arm.pat {
+                Pat::Or(or) if self.needs_split(or) => {
+                    for case in &or.cases {
+                        arms.push(Arm { pat: case.clone(), ..arm.clone() });
+                    }
+                },
+                _ => arms.push(arm),
+            }
+        }
+        i.arms = self.lower_subs(arms);
+
+        visit_expr_match_mut(self, i);
+    }
+
     // fixup matches:
     // `Foo { x } => { ... }` ==> `Foo { x } => { let x = x.extract(); ... }`
+    // The same happens for guards: `Foo { x } if x.f() => ...` ==> `Foo { x } if { let x = x.extract(); x.f() } => ...`
+    // Sub-patterns of wrapped elements were lowered to nested matches already, see `lower_subs`.
     fn visit_arm_mut(&mut self, i: &mut Arm) {
-        let idents: Vec<_> = pat_idents::pat_idents(&i.pat, self.elements).into_iter().collect();
-        let body = &i.body;
-        let body = quote! {
-            {
-                #( let #idents = #idents.extract(); )*
-                #body
+        let idents = self.pat_idents(&mut i.pat);
+
+        let extracts = self.extracts(idents.clone(), &i.body, Subs::Checked);
+        if !extracts.is_empty() {
+            let body = &i.body;
+            i.body = parse_quote! { { #( #extracts )* #body } };
+        }
+        if let Some((_, guard)) = &mut i.guard {
+            let extracts = self.extracts(idents, &guard, Subs::Checked);
+            if !extracts.is_empty() {
+                *guard = parse_quote! { { #( #extracts )* #guard } };
             }
-        };
-        i.body = Box::new(parse2(body).unwrap());
+        }
 
         visit_arm_mut(self, i);
     }
+
+    // fixup `if let`, including let chains:
+    // `if let Foo { x } = e { ... }` ==> `if let Foo { x } = e { let x = x.extract(); ... }`
+    fn visit_expr_if_mut(&mut self, i: &mut ExprIf) {
+        self.fix_cond(&mut i.cond, &mut i.then_branch);
+
+        visit_expr_if_mut(self, i);
+    }
+
+    // fixup `while let`, like `if let`.
+    fn visit_expr_while_mut(&mut self, i: &mut ExprWhile) {
+        self.fix_cond(&mut i.cond, &mut i.body);
+
+        visit_expr_while_mut(self, i);
+    }
+
+    // fixup `for` loops:
+    // `for Foo { x } in e { ... }` ==> `for Foo { x } in e { let x = x.extract(); ... }`
+    fn visit_expr_for_loop_mut(&mut self, i: &mut ExprForLoop) {
+        let idents = self.pat_idents(&mut i.pat);
+        let extracts = self.extracts(idents, &i.body, Subs::Irrefutable);
+        i.body.stmts.splice(0..0, extracts);
+
+        visit_expr_for_loop_mut(self, i);
+    }
+
+    // fixup `let` statements, including `let`-`else`:
+    // `let Foo { x } = e else { ... };` ==> `let Foo { x } = e else { ... }; let x = x.extract();`
+    // The `use` declarations of the block are taken into account when resolving paths.
+    fn visit_block_mut(&mut self, i: &mut Block) {
+        let n = self.cx.scope.uses.len();
+        for stmt in &i.stmts {
+            if let Stmt::Item(Item::Use(u)) = stmt {
+                self.cx.scope.uses.extend(resolve::uses(u));
+            }
+        }
+
+        let mut stmts = Vec::new();
+        let mut old = std::mem::take(&mut i.stmts);
+        for j in 0..old.len() {
+            let (done, rest) = old.split_at_mut(j + 1);
+            let stmt = &mut done[j];
+            let extracts = match stmt {
+                Stmt::Local(local) if !unwraps_fresh(local) => {
+                    let idents = self.pat_idents(&mut local.pat);
+                    let diverge = local.init.as_ref().and_then(|init| init.diverge.as_ref()).map(|(_, d)| (**d).clone());
+                    let subs = diverge.as_ref().map_or(Subs::Irrefutable, Subs::Else);
+                    // the pattern might have become irrefutable by replacing its sub-patterns, like `let S(Foo::A) = s else { .. }`.
+                    if diverge.is_some() && !idents.subs.is_empty() {
+                        local.attrs.push(parse_quote! { #[allow(irrefutable_let_patterns)] });
+                    }
+                    self.extracts(idents, &quote! { #( #rest )* }, subs)
+                },
+                _ => Vec::new(),
+            };
+            stmts.push(stmt.clone());
+            stmts.extend(extracts);
+        }
+        i.stmts = stmts;
+
+        visit_block_mut(self, i);
+        self.cx.scope.uses.truncate(n);
+    }
+
+    // paths within inline modules are resolved relative to them.
+    fn visit_item_mod_mut(&mut self, i: &mut ItemMod) {
+        self.cx.scope.module.push(i.ident.to_string());
+        visit_item_mod_mut(self, i);
+        self.cx.scope.module.pop();
+    }
+
+    // `Self` refers to the type of the impl block.
+    fn visit_item_impl_mut(&mut self, i: &mut ItemImpl) {
+        let self_ty = match &*i.self_ty {
+            Type::Path(p) if p.qself.is_none() => match self.cx.resolver.resolve(&self.cx.scope, &p.path) {
+                Resolution::Found(res) => Some(res),
+                _ => None,
+            },
+            _ => None,
+        };
+        let old = std::mem::replace(&mut self.cx.scope.self_ty, self_ty);
+        visit_item_impl_mut(self, i);
+        self.cx.scope.self_ty = old;
+    }
+
+    // `Self` is unknown within traits.
+    fn visit_item_trait_mut(&mut self, i: &mut ItemTrait) {
+        let old = self.cx.scope.self_ty.take();
+        visit_item_trait_mut(self, i);
+        self.cx.scope.self_ty = old;
+    }
+
+    // fixup closure parameters:
+    // `|Foo { x }| ...` ==> `|Foo { x }| { let x = x.extract(); ... }`
+    fn visit_expr_closure_mut(&mut self, i: &mut ExprClosure) {
+        let mut extracts = Vec::new();
+        for p in i.inputs.iter_mut() {
+            let idents = self.pat_idents(p);
+            extracts.extend(self.extracts(idents, &i.body, Subs::Irrefutable));
+        }
+        if !extracts.is_empty() {
+            let body = &i.body;
+            i.body = parse_quote! { { #( #extracts )* #body } };
+        }
+
+        visit_expr_closure_mut(self, i);
+    }
+
+    // fixup function parameters:
+    // `fn f(Foo { x }: Foo) { ... }` ==> `fn f(Foo { x }: Foo) { let x = x.extract(); ... }`
+    fn visit_item_fn_mut(&mut self, i: &mut ItemFn) {
+        self.fix_params(&mut i.sig, &mut i.block);
+
+        visit_item_fn_mut(self, i);
+    }
+
+    fn visit_impl_item_fn_mut(&mut self, i: &mut ImplItemFn) {
+        self.fix_params(&mut i.sig, &mut i.block);
+
+        visit_impl_item_fn_mut(self, i);
+    }
+
+    fn visit_trait_item_fn_mut(&mut self, i: &mut TraitItemFn) {
+        if let Some(block) = &mut i.default {
+            self.fix_params(&mut i.sig, block);
+        }
+
+        visit_trait_item_fn_mut(self, i);
+    }
+
+    // fixup the arguments of macros like `list![Foo { x: 2 }]`, see `visit_macro_args`.
+    // Also fixup guards of `matches!`, like the guards of match arms:
+    // `matches!(e, Foo { x } if x.f())` ==> `matches!(e, Foo { x } if { let x = x.extract(); x.f() })`
+    fn visit_macro_mut(&mut self, i: &mut Macro) {
+        if i.path.segments.last().map_or(false, |s| s.ident == "matches") {
+            if let Ok(mut m) = i.parse_body::<Matches>() {
+                visit_expr_mut(self, &mut m.expr);
+                let idents = self.pat_idents(&mut m.pat);
+                let cond = self.cond(&idents.subs);
+                if let Some(guard) = &mut m.guard {
+                    let extracts = self.extracts(idents, &guard, Subs::Checked);
+                    if !extracts.is_empty() {
+                        *guard = parse_quote! { { #( #extracts )* #guard } };
+                    }
+                    visit_expr_mut(self, guard);
+                }
+                if let Some(cond) = cond {
+                    m.guard = Some(match &m.guard {
+                        Some(guard) => parse_quote! { #cond && #guard },
+                        None => cond,
+                    });
+                }
+                i.tokens = m.to_token_stream();
+            }
+        } else {
+            visit_macro_args(self, i);
+        }
+
+        visit_macro_mut(self, i);
+    }
+}
+
+// how `Visitor::extracts` matches sub-patterns.
+#[derive(Clone, Copy)]
+enum Subs<'a> {
+    // the pattern has to match, like in a `let` statement.
+    Irrefutable,
+    // the pattern is known to match, as it was checked by the guard of a match arm.
+    Checked,
+    // the pattern might not match, in which case the given block of a `let`-`else` is executed.
+    Else(&'a Expr),
+}
+
+impl Visitor<'_> {
+    fn pat_idents(&mut self, pat: &mut Pat) -> pat_idents::PatIdents {
+        pat_idents::pat_idents(pat, &mut self.cx, &mut self.fresh)
+    }
+
+    // returns the statements which unwrap the wrapped elements of a pattern, given its `idents`:
+    // `let x = x.extract();` for each binding `x` which is used in `scope`,
+    // and `let p = i.extract();` for each sub-pattern `p`, which was replaced by the binding `i`.
+    fn extracts(&mut self, idents: pat_idents::PatIdents, scope: &impl ToTokens, subs: Subs) -> Vec<Stmt> {
+        let scope = scope.to_token_stream();
+        let mut stmts: Vec<Stmt> = idents.binds.into_iter()
+            .filter(|x| contains_ident(&scope, &x.ident))
+            .map(|x| {
+                let name = &x.ident;
+                parse_quote! { let #x = #name.extract(); }
+            })
+            .collect();
+
+        for (i, mut sub) in idents.subs {
+            // sub-patterns which were checked already are only needed for their bindings.
+            if let Subs::Checked = subs {
+                if !pat_idents::bindings(&sub).iter().any(|x| contains_ident(&scope, x)) { continue; }
+            }
+
+            let inner = self.pat_idents(&mut sub);
+            let diverge = match subs {
+                Subs::Irrefutable => None,
+                Subs::Checked => Some(parse_quote! { { unreachable!() } }),
+                Subs::Else(diverge) => Some(diverge.clone()),
+            };
+            stmts.push(match diverge {
+                Some(diverge) => parse_quote! {
+                    #[allow(irrefutable_let_patterns)]
+                    let #sub = #i.extract() else #diverge;
+                },
+                None => parse_quote! { let #sub = #i.extract(); },
+            });
+            stmts.extend(self.extracts(inner, &scope, subs));
+        }
+
+        stmts
+    }
+
+    // returns the condition under which the sub-patterns `subs` match, if there are any:
+    // `match i.extract() { p => true, _ => false }` for each sub-pattern `p`, which was replaced by the binding `i`.
+    fn cond(&mut self, subs: &[(Ident, Pat)]) -> Option<Expr> {
+        subs.iter().map(|(i, sub)| {
+            let mut sub = sub.clone();
+            let inner = self.pat_idents(&mut sub);
+            let guard = self.cond(&inner.subs).map(|c| quote! { if #c });
+            parse_quote! {
+                match #i.extract() {
+                    #[allow(unused_variables)]
+                    #sub #guard => true,
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }
+            }
+        }).reduce(|a: Expr, b| parse_quote! { #a && #b })
+    }
+
+    // lowers the sub-patterns of wrapped elements in the match arms `arms` to guards matching the extracted values,
+    // so that a value which they don't match falls through to the remaining arms:
+    // `Foo { x: Bar(y) } if g => body` ==>
+    // `Foo { x: i } if match i.extract() { Bar(y) => true, _ => false } && { let Bar(y) = i.extract() else { .. }; g } => { let Bar(y) = ..; body }`
+    // rustc ignores guards when checking exhaustiveness, so `Foo { x: i } => unreachable!()` is added after all arms for each such arm.
+    // This way, rustc still checks that the arms are exhaustive apart from the sub-patterns, and a value none of the arms matches panics.
+    // Each arm is lowered only once, so the match grows linearly with the number of arms.
+    fn lower_subs(&mut self, arms: Vec<Arm>) -> Vec<Arm> {
+        let mut lowered = Vec::new();
+        let mut fallbacks = Vec::new();
+        for mut arm in arms {
+            let subs = self.pat_idents(&mut arm.pat).subs;
+            let Some(cond) = self.cond(&subs) else {
+                lowered.push(arm);
+                continue;
+            };
+
+            let idents = pat_idents::PatIdents { binds: Vec::new(), subs };
+            let extracts = self.extracts(idents.clone(), &arm.body, Subs::Checked);
+            let body = &arm.body;
+            arm.body = parse_quote! { { #( #extracts )* #body } };
+            arm.guard = Some((Default::default(), Box::new(match arm.guard.take() {
+                Some((_, guard)) => {
+                    let extracts = self.extracts(idents, &guard, Subs::Checked);
+                    parse_quote! { #cond && { #( #extracts )* #guard } }
+                },
+                None => cond,
+            })));
+            arm.comma = Some(Default::default());
+
+            let pat = &arm.pat;
+            fallbacks.push(parse_quote! {
+                #[allow(unused_variables, unreachable_patterns)]
+                #pat => unreachable!(),
+            });
+            lowered.push(arm);
+        }
+        lowered.extend(fallbacks);
+
+        lowered
+    }
+
+    // whether the alternatives of `or` need to become separate match arms, see `visit_expr_match_mut`.
+    fn needs_split(&mut self, or: &PatOr) -> bool {
+        let mut binds = Vec::new();
+        for case in &or.cases {
+            let idents = pat_idents::pat_idents(&mut case.clone(), &mut self.cx, &mut 0);
+            // the fresh bindings would only exist in this alternative.
+            if !idents.subs.is_empty() { return true; }
+
+            let mut b: Vec<Ident> = idents.binds.into_iter().map(|b| b.ident).collect();
+            b.sort();
+            binds.push(b);
+        }
+
+        binds.iter().any(|b| *b != binds[0])
+    }
+
+    fn fix_params(&mut self, sig: &mut Signature, block: &mut Block) {
+        let mut extracts = Vec::new();
+        for arg in sig.inputs.iter_mut() {
+            let FnArg::Typed(pt) = arg else { continue };
+            let idents = self.pat_idents(&mut pt.pat);
+            extracts.extend(self.extracts(idents, &*block, Subs::Irrefutable));
+        }
+        block.stmts.splice(0..0, extracts);
+    }
+
+    // fixes the condition `cond` of an `if` or `while` with the body `body`.
+    // In a let chain like `let Foo { x } = e && x.f()`, the bindings have to be unwrapped before the next condition,
+    // so `let x = x.extract()` is inserted into the chain right after the `let`.
+    // Sub-patterns `p`, which were replaced by the binding `i`, are matched by inserting `let p = i.extract()` as well.
+    // Irrefutable `let`s at the end of the chain are moved into the body, as they would cause a warning.
+    fn fix_cond(&mut self, cond: &mut Expr, body: &mut Block) {
+        let mut conds = Vec::new();
+        flatten_and(std::mem::replace(cond, Expr::Verbatim(TokenStream::new())), &mut conds);
+
+        // the fixed conditions, and whether they are irrefutable extracts.
+        let mut fixed = Vec::new();
+        let scope = quote! { #( #conds )* #body };
+        for c in conds {
+            self.fix_cond_part(c, &scope, &mut fixed);
+        }
+
+        let mut trailing = Vec::new();
+        while fixed.len() > 1 && fixed.last().unwrap().1 {
+            let (c, _) = fixed.pop().unwrap();
+            let Expr::Let(l) = c else { unreachable!() };
+            let (pat, expr) = (&l.pat, &l.expr);
+            trailing.insert(0, parse_quote! { let #pat = #expr; });
+        }
+        body.stmts.splice(0..0, trailing);
+
+        *cond = fixed.into_iter().map(|(c, _)| c).reduce(|a, b| parse_quote! { #a && #b }).unwrap();
+    }
+
+    // pushes the condition `c` of a let chain to `out`, followed by the `let`s unwrapping its bindings.
+    fn fix_cond_part(&mut self, c: Expr, scope: &TokenStream, out: &mut Vec<(Expr, bool)>) {
+        let Expr::Let(mut l) = c else {
+            out.push((c, false));
+            return;
+        };
+        let idents = self.pat_idents(&mut l.pat);
+        out.push((Expr::Let(l), false));
+
+        for (i, sub) in idents.subs {
+            self.fix_cond_part(parse_quote! { let #sub = #i.extract() }, scope, out);
+        }
+        for x in idents.binds {
+            if !contains_ident(scope, &x.ident) { continue; }
+            let name = &x.ident;
+            out.push((parse_quote! { let #x = #name.extract() }, true));
+        }
+    }
+}
+
+// whether `local` is a `let p = i.extract()` generated by `extracts`, for a fresh binding `i`.
+// Its pattern was already handled.
+fn unwraps_fresh(local: &Local) -> bool {
+    let Some(init) = &local.init else { return false };
+    let Expr::MethodCall(call) = &*init.expr else { return false };
+    let Expr::Path(p) = &*call.receiver else { return false };
+
+    call.method == "extract" && p.path.get_ident().map_or(false, pat_idents::is_fresh)
+}
+
+// whether `ident` occurs anywhere in `tokens`.
+fn contains_ident(tokens: &TokenStream, ident: &Ident) -> bool {
+    tokens.clone().into_iter().any(|t| match t {
+        TokenTree::Ident(i) => i == *ident,
+        TokenTree::Group(g) => contains_ident(&g.stream(), ident),
+        _ => false,
+    })
+}
+
+// splits `a && b && c` into its conditions `[a, b, c]`.
+fn flatten_and(e: Expr, out: &mut Vec<Expr>) {
+    match e {
+        Expr::Binary(ExprBinary { left, op: BinOp::And(_), right, attrs }) if attrs.is_empty() => {
+            flatten_and(*left, out);
+            flatten_and(*right, out);
+        },
+        e => out.push(e),
+    }
+}
+
+// the arguments of `matches!(expr, pat if guard)`.
+struct Matches {
+    expr: Expr,
+    pat: Pat,
+    guard: Option<Expr>,
+}
+
+impl parse::Parse for Matches {
+    fn parse(input: parse::ParseStream) -> Result<Matches> {
+        let expr = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let pat = Pat::parse_multi_with_leading_vert(input)?;
+        let guard = if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        input.parse::<Option<Token![,]>>()?;
+
+        Ok(Matches { expr, pat, guard })
+    }
+}
+
+impl ToTokens for Matches {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let Matches { expr, pat, guard } = self;
+        let guard = guard.as_ref().map(|g| quote! { if #g });
+        tokens.extend(quote! { #expr, #pat #guard });
+    }
 }
 
 // wraps an Expr in libspecr::hidden::GcCow::new(_)
@@ -77,9 +523,54 @@ fn wrap_expr(expr: &mut Expr) {
     *expr = parse2(e).unwrap();
 }
 
-// extract the last segment (i.e. the enum Variant) from a path
-// `Foo::Bar` => `Bar`
-pub(in crate::typerec) fn extract_variant(p: &Path) -> Ident {
-    let p = p.segments.iter().last().unwrap(); 
-    p.ident.clone()
+#[cfg(test)]
+mod tests {
+    use crate::transpile_rust;
+
+    // the code generated for `f`, which matches on a `Chain`.
+    fn lang(f: &str) -> String {
+        let chain = "pub enum Chain { Cons(Int, #[specr::indirection] Chain), Nil }";
+        let t = transpile_rust(&[("lang/a.md", &format!("{chain}\n{f}"))]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        t.code("src/lang.rs")
+    }
+
+    fn squash(s: &str) -> String {
+        s.split_whitespace().collect()
+    }
+
+    #[test]
+    fn sub_pattern() {
+        let code = lang("fn f(c: Chain) -> Int { match c { Chain::Cons(_, Chain::Cons(y, _)) if y > 0 => y, _ => 0 } }");
+        assert!(code.contains(&squash("
+            Chain::Cons(_, __specr_ind0,)
+            if match __specr_ind0.extract() {
+                #[allow(unused_variables)]
+                Chain::Cons(y, _) => true,
+                #[allow(unreachable_patterns)]
+                _ => false,
+            } && {
+                #[allow(irrefutable_let_patterns)]
+                let Chain::Cons(y, _) = __specr_ind0.extract() else { unreachable!() };
+                y > 0
+            } => {
+                #[allow(irrefutable_let_patterns)]
+                let Chain::Cons(y, _) = __specr_ind0.extract() else { unreachable!() };
+                y
+            }
+            _ => 0,
+            #[allow(unused_variables, unreachable_patterns)]
+            Chain::Cons(_, __specr_ind0) => unreachable!(),
+        ")), "{code}");
+    }
+
+    // each arm is lowered once, so that the code grows linearly with the number of arms.
+    #[test]
+    fn many_arms() {
+        let arms: String = (0..7).map(|k| format!("Chain::Cons(x, Chain::Cons(y, _)) if x == {k} => y,")).collect();
+        let code = lang(&format!("fn f(c: Chain) -> Int {{ match c {{ {arms} _ => 0 }} }}"));
+        // the fallback arm of each arm, and the `let`-`else` extracting `y` in its body.
+        assert_eq!(code.matches("unreachable!()").count(), 2 * 7);
+        assert!(code.len() < 7 * 600, "{code}");
+    }
 }
@@ -1,42 +1,142 @@
 use crate::prelude::*;
+use crate::config::AutoIndirection;
+use crate::resolve::{Res, Resolution, Resolver, Scope};
+use std::collections::HashMap;
+
+mod detect;
 
 mod wrap;
-use wrap::wrap_variant_elements;
+use wrap::wrap_elements;
 
 mod pat_idents;
 mod fix;
+mod access;
 
-/// Resolves infinite type recursion problems in enums by wrapping with `GcCow<_>`.
-pub fn typerec(mut mods: Vec<Module>) -> Vec<Module> {
-    let elements = wrap_variant_elements(&mut mods);
-    fix::fix(&mut mods, &elements);
+/// Resolves infinite type recursion problems in enums and structs by wrapping with `GcCow<_>`.
+/// Recursive types without `#[specr::indirection]` are found and handled according to `auto_indirection`.
+/// Paths to the wrapped variants and structs are resolved first, see `Cx::wrapped`.
+pub fn typerec(mut mods: Vec<Module>, auto_indirection: AutoIndirection, diags: &mut Diagnostics) -> Vec<Module> {
+    let resolver = Resolver::new(&mods);
+    detect::detect(&mut mods, &resolver, auto_indirection, diags);
+    let (elements, members, arities) = wrap_elements(&mut mods);
+    fix::fix(&mut mods, &elements, &arities, &resolver, diags);
+    access::access(&mut mods, &elements, &members, &resolver, diags);
 
     mods
 }
 
-/// Specifies an Element of some enum Variant.
-/// Note that this does not know the name of the enum.
+/// Specifies an Element of some enum Variant or struct.
 ///
 /// Example:
+/// mod lang;
 /// enum Foo {
 ///   A { a: u32 },
 ///   B(u32),
 /// }
+/// struct Bar(u32);
 ///
 /// referencing Foo::A::a would be done by
-/// VariantElement { variant: "A", idx: ElementIdx::Named("a") } 
+/// Element { path: ["lang", "Foo", "A"], idx: ElementIdx::Named("a") }
 /// while the u32-argument of Foo::B would be referenced by
-/// VariantElement { variant: "B", idx: ElementIdx::Unnamed(0) } 
+/// Element { path: ["lang", "Foo", "B"], idx: ElementIdx::Unnamed(0) }
+/// and the one of Bar by
+/// Element { path: ["lang", "Bar"], idx: ElementIdx::Unnamed(0) }
 ///
 #[derive(Hash, PartialEq, Eq)]
-struct VariantElement {
-    variant: Ident,
+struct Element {
+    /// the full path of the variant or struct, starting at the crate root.
+    path: Vec<String>,
     idx: ElementIdx,
 }
 
-/// Indexes an enum variant, either by-name, or by argument position.
+/// Indexes an enum variant or struct, either by-name, or by argument position.
 #[derive(Hash, PartialEq, Eq)]
 enum ElementIdx {
     Named(Ident),
     Unnamed(usize),
 }
+
+/// Finds the wrapped variants and structs which paths refer to.
+struct Cx<'a> {
+    elements: &'a HashSet<Element>,
+    /// the number of fields of the variants and structs with wrapped elements,
+    /// which tells the position of the elements after `..` in a pattern like `Foo(.., x)`.
+    arities: &'a HashMap<Vec<String>, usize>,
+    resolver: &'a Resolver,
+    /// where the paths currently visited are resolved.
+    scope: Scope,
+    /// the errors found so far.
+    /// Paths can be visited several times, so this has no duplicates.
+    errors: Vec<Diagnostic>,
+}
+
+impl Cx<'_> {
+    /// The full path of the variant or struct `path` refers to, if it has wrapped elements.
+    ///
+    /// It's an error if `path` is ambiguous, or if it can't be resolved while being qualified:
+    /// it might refer to a wrapped variant, and we can't tell.
+    /// Unresolved paths like `Some` are assumed to come from another crate.
+    fn wrapped(&mut self, path: &Path) -> Option<Vec<String>> {
+        let name = path.segments.last()?.ident.to_string();
+        let is_wrapped = |p: &Vec<String>| self.elements.iter().any(|e| e.path == *p);
+        // `Self` can only refer to a struct.
+        if name != "Self" && !self.elements.iter().any(|e| *e.path.last().unwrap() == name) { return None; }
+
+        let path_str = path.to_token_stream().to_string().replace(' ', "");
+        let err = match self.resolver.resolve(&self.scope, path) {
+            Resolution::Found(res) => {
+                let p = full_path(res)?;
+                return is_wrapped(&p).then_some(p);
+            },
+            Resolution::Ambiguous(candidates) => {
+                if !candidates.iter().cloned().filter_map(full_path).any(|p| is_wrapped(&p)) { return None; }
+                let candidates: Vec<String> = candidates.iter().map(|c| format!("`{}`", c.to_path_string())).collect();
+                Diagnostic::error(format!("`{path_str}` is ambiguous"))
+                    .note(format!("it could refer to {}", candidates.join(" or ")))
+            },
+            Resolution::Unknown if path.segments.len() == 1 => return None,
+            Resolution::Unknown => {
+                let mut wrapped: Vec<String> = self.elements.iter()
+                    .filter(|e| *e.path.last().unwrap() == name)
+                    .map(|e| format!("`{}`", e.path.join("::")))
+                    .collect();
+                wrapped.sort();
+                wrapped.dedup();
+                Diagnostic::error(format!("failed to resolve `{path_str}`"))
+                    .note(format!("it might refer to {}", wrapped.join(" or ")))
+            },
+        };
+        let err = err.at(path.segments[0].ident.span())
+            .note(format!("specr needs to know whether `{path_str}` has fields with `#[specr::indirection]`; use its full path, like `crate::module::Enum::{name}`"));
+        if !self.errors.contains(&err) {
+            self.errors.push(err);
+        }
+
+        None
+    }
+}
+
+/// Visits the arguments of the macro call `mac` with `v`, if they can be parsed as comma-separated expressions,
+/// like those of `list![a, b]` or `assert_eq!(a, b)`.
+fn visit_macro_args(v: &mut impl VisitMut, mac: &mut Macro) {
+    let Ok(mut args) = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated) else { return };
+    let old = args.to_token_stream().to_string();
+    for arg in args.iter_mut() {
+        v.visit_expr_mut(arg);
+    }
+    if args.to_token_stream().to_string() != old {
+        mac.tokens = args.to_token_stream();
+    }
+}
+
+// the full path of a variant or struct.
+fn full_path(res: Res) -> Option<Vec<String>> {
+    match res {
+        Res::Variant(mut e, v) => {
+            e.push(v);
+            Some(e)
+        },
+        Res::Struct(s) => Some(s),
+        _ => None,
+    }
+}
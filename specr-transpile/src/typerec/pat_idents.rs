@@ -1,53 +1,144 @@
 use crate::typerec::*;
 
+/// The parts of a pattern which match on wrapped elements, and hence need to be `extract`ed.
+#[derive(Clone)]
+pub(in crate::typerec) struct PatIdents {
+    /// plain bindings of wrapped elements like `x` or `mut x`, in the order they appear in the pattern.
+    pub binds: Vec<PatIdent>,
+    /// sub-patterns of wrapped elements like `Foo { .. }` or `x @ Foo { .. }`,
+    /// which were replaced by a fresh binding in the pattern.
+    /// They have to be matched against the extracted value of that binding.
+    pub subs: Vec<(Ident, Pat)>,
+}
+
 /// finds identifiers within a pattern that need to be get'ed before being used.
-pub(in crate::typerec) fn pat_idents(pat: &Pat, elements: &HashSet<VariantElement>) -> HashSet<Ident> {
+/// Sub-patterns of wrapped elements are replaced by fresh bindings named `__specr_ind<n>`, counting up from `fresh`.
+pub(in crate::typerec) fn pat_idents(pat: &mut Pat, cx: &mut Cx, fresh: &mut usize) -> PatIdents {
     let mut v = Visitor {
-        elements,
-        idents: HashSet::new(),
+        cx,
+        fresh,
+        idents: PatIdents { binds: Vec::new(), subs: Vec::new() },
     };
-    v.visit_pat(pat);
+    v.visit_pat_mut(pat);
 
     v.idents
 }
 
-struct Visitor<'a> {
-    elements: &'a HashSet<VariantElement>,
-    idents: HashSet<Ident>,
+/// The names bound by `pat`.
+pub(in crate::typerec) fn bindings(pat: &Pat) -> Vec<Ident> {
+    struct Bindings(Vec<Ident>);
+
+    impl Visit<'_> for Bindings {
+        fn visit_pat_ident(&mut self, pat: &PatIdent) {
+            self.0.push(pat.ident.clone());
+            visit_pat_ident(self, pat);
+        }
+    }
+
+    let mut b = Bindings(Vec::new());
+    b.visit_pat(pat);
+
+    b.0
 }
 
-impl Visit<'_> for Visitor<'_> {
-    fn visit_pat_struct(&mut self, pat: &PatStruct) {
-        let var = fix::extract_variant(&pat.path);
-        for e in self.elements {
-            if e.variant != var { continue; }
-            let ElementIdx::Named(n) = &e.idx else { continue };
+/// Whether `ident` is one of the fresh bindings introduced by `pat_idents`.
+pub(in crate::typerec) fn is_fresh(ident: &Ident) -> bool {
+    ident.to_string().starts_with("__specr_ind")
+}
 
-            for f in &pat.fields {
-                let Member::Named(m) = &f.member else { continue };
-                if m != n { continue; }
+struct Visitor<'a, 'b> {
+    cx: &'a mut Cx<'b>,
+    fresh: &'a mut usize,
+    idents: PatIdents,
+}
 
-                if let Pat::Ident(id) = &*f.pat {
-                    self.idents.insert(id.ident.clone());
+impl Visitor<'_, '_> {
+    // handles the pattern `pat` of the wrapped element `e`, returns whether `pat` was handled.
+    fn element(&mut self, e: Element, pat: &mut Pat) -> bool {
+        if !self.cx.elements.contains(&e) { return false; }
+
+        match pat {
+            // this was introduced by us, and is already taken care of.
+            Pat::Ident(id) if is_fresh(&id.ident) => {},
+            Pat::Ident(id) if id.subpat.is_none() && id.by_ref.is_none() => {
+                if !self.idents.binds.iter().any(|b| b.ident == id.ident) {
+                    self.idents.binds.push(id.clone());
                 }
-            }
+            },
+            Pat::Wild(_) | Pat::Rest(_) => {},
+            _ => {
+                let ident = format_ident!("__specr_ind{}", *self.fresh);
+                *self.fresh += 1;
+                let sub = std::mem::replace(pat, parse_quote! { #ident });
+                self.idents.subs.push((ident, sub));
+            },
         }
 
-        visit_pat_struct(self, pat);
+        true
     }
+}
 
-    fn visit_pat_tuple_struct(&mut self, pat: &PatTupleStruct) {
-        let var = fix::extract_variant(&pat.path);
-        for e in self.elements {
-            if e.variant != var { continue; }
-            let ElementIdx::Unnamed(idx) = &e.idx else { continue };
+impl VisitMut for Visitor<'_, '_> {
+    fn visit_pat_struct_mut(&mut self, pat: &mut PatStruct) {
+        let Some(path) = self.cx.wrapped(&pat.path) else { return visit_pat_struct_mut(self, pat) };
+        for f in &mut pat.fields {
+            let Member::Named(m) = &f.member else { continue };
+            let e = Element { path: path.clone(), idx: ElementIdx::Named(m.clone()) };
+            let shorthand = f.colon_token.is_none();
+            if self.element(e, &mut f.pat) {
+                // `Foo { x: y }` can't be written as `Foo { x }` anymore, if the pattern was replaced.
+                if shorthand && !matches!(&*f.pat, Pat::Ident(id) if id.ident == *m) {
+                    f.colon_token = Some(Default::default());
+                }
+            } else {
+                self.visit_field_pat_mut(f);
+            }
+        }
+    }
 
-            let Some(f) = pat.elems.iter().nth(*idx) else { continue };
-            if let Pat::Ident(id) = f {
-                self.idents.insert(id.ident.clone());
+    fn visit_pat_tuple_struct_mut(&mut self, pat: &mut PatTupleStruct) {
+        let Some(path) = self.cx.wrapped(&pat.path) else { return visit_pat_tuple_struct_mut(self, pat) };
+        let n = pat.elems.len();
+        let rest = pat.elems.iter().position(|p| matches!(p, Pat::Rest(_)));
+        let arity = self.cx.arities.get(&path).copied();
+        for (i, f) in pat.elems.iter_mut().enumerate() {
+            // the elements after `..` are at the end of the variant or struct.
+            let idx = match (rest, arity) {
+                (Some(r), Some(arity)) if i > r => (arity + i).checked_sub(n),
+                _ => Some(i),
+            };
+            let handled = match idx {
+                Some(idx) => self.element(Element { path: path.clone(), idx: ElementIdx::Unnamed(idx) }, f),
+                None => false,
+            };
+            if !handled {
+                self.visit_pat_mut(f);
             }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile_rust;
+
+    // the code generated for `f`, which matches on a `Tree`, without whitespace.
+    fn lang(f: &str) -> String {
+        let tree = "pub enum Tree { Node(Int, #[specr::indirection] Tree, Int), Leaf }";
+        let t = transpile_rust(&[("lang/a.md", &format!("{tree}\n{f}"))]);
+        assert_eq!(t.messages(), Vec::<&str>::new());
+        t.code("src/lang.rs")
+    }
+
+    #[test]
+    fn rest() {
+        // `l` is the wrapped element, `y` is not.
+        let code = lang("fn f(t: Tree) -> Int { let Tree::Node(_, .., l, _) = t else { return 0 }; let Tree::Node(.., y) = l else { return 1 }; y }");
+        assert!(code.contains("letl=l.extract();"), "{code}");
+        assert!(!code.contains("lety=y.extract();"), "{code}");
 
-        visit_pat_tuple_struct(self, pat);
+        let code = lang("fn f(t: Tree) -> Tree { match t { Tree::Node(_, .., Tree::Leaf, _) => t, Tree::Node(a, ..) => t, _ => t } }");
+        assert!(code.contains("Tree::Node(_,..,__specr_ind0,_,)ifmatch__specr_ind0.extract()"), "{code}");
+        assert!(code.contains("Tree::Node(a,..)=>t"), "{code}");
     }
 }
@@ -1,55 +1,81 @@
 use crate::typerec::*;
+use std::collections::HashMap;
 
-// TODO warn whenever two variant-elements could be confused.
-
-/// Wraps enum variant elements marked with `#[specr::indirection]`.
-pub(in crate::typerec) fn wrap_variant_elements(mods: &mut [Module]) -> HashSet<VariantElement> {
+/// Wraps enum variant and struct elements marked with `#[specr::indirection]`.
+/// Returns these elements, the names of the struct fields among them,
+/// and the number of fields of the tuple variants and structs among them, see `Cx::arities`.
+pub(in crate::typerec) fn wrap_elements(mods: &mut [Module]) -> (HashSet<Element>, HashSet<Member>, HashMap<Vec<String>, usize>) {
     let mut elements = HashSet::new();
+    let mut members = HashSet::new();
+    let mut arities = HashMap::new();
 
     for m in mods {
-        for item in &mut m.ast.items {
-            if let Item::Enum(it_enum) = item {
-                elements.extend(wrap_enum(it_enum));
-            }
-        }
+        wrap_items(&m.full_path(), &mut m.ast.items, &mut elements, &mut members, &mut arities);
     }
 
-    elements
+    (elements, members, arities)
 }
 
-fn is_indirection_attr(attr: &Attribute) -> bool {
+fn wrap_items(module: &[String], items: &mut [Item], elements: &mut HashSet<Element>, members: &mut HashSet<Member>, arities: &mut HashMap<Vec<String>, usize>) {
+    for item in items {
+        let item_path = |ident: &Ident| {
+            let mut path = module.to_vec();
+            path.push(ident.to_string());
+            path
+        };
+        match item {
+            Item::Enum(it_enum) => {
+                let enum_path = item_path(&it_enum.ident);
+                for variant in &mut it_enum.variants {
+                    let mut path = enum_path.clone();
+                    path.push(variant.ident.to_string());
+                    if !wrap_fields(&path, &mut variant.fields, elements).is_empty() {
+                        arities.insert(path, variant.fields.len());
+                    }
+                }
+            },
+            Item::Struct(it_struct) => {
+                let path = item_path(&it_struct.ident);
+                let wrapped = wrap_fields(&path, &mut it_struct.fields, elements);
+                if !wrapped.is_empty() {
+                    arities.insert(path, it_struct.fields.len());
+                }
+                members.extend(wrapped);
+            },
+            Item::Mod(m) => {
+                if let Some((_, items)) = &mut m.content {
+                    wrap_items(&item_path(&m.ident), items, elements, members, arities);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+pub(in crate::typerec) fn is_indirection_attr(attr: &Attribute) -> bool {
     let s = format!("{}", attr.path().to_token_stream()).replace(" ", "");
     s == "specr::indirection"
 }
 
-fn wrap_enum(it_enum: &mut ItemEnum) -> HashSet<VariantElement> {
-    let mut elements = HashSet::new();
+// wraps the marked `fields` of the variant or struct `path`, and returns their names.
+fn wrap_fields(path: &[String], fields: &mut Fields, elements: &mut HashSet<Element>) -> Vec<Member> {
+    let mut members = Vec::new();
+    for (i, f) in fields.iter_mut().enumerate() {
+        if let Some(j) = f.attrs.iter().position(is_indirection_attr) {
+            f.attrs.remove(j);
 
-    for variant in &mut it_enum.variants {
-        let fields: Vec<&mut Field> = match &mut variant.fields {
-            Fields::Named(x) => x.named.iter_mut().collect(),
-            Fields::Unnamed(x) => x.unnamed.iter_mut().collect(),
-            Fields::Unit => Vec::new(),
-        };
+            let t = &f.ty;
+            let wrapped_ty = quote! { libspecr::hidden::GcCow<#t> };
+            f.ty = parse2(wrapped_ty).unwrap();
 
-        for (i, f) in fields.into_iter().enumerate() {
-            if let Some(j) = f.attrs.iter().position(is_indirection_attr) {
-                f.attrs.remove(j);
-
-                let t = &f.ty;
-                let wrapped_ty = quote! { libspecr::hidden::GcCow<#t> };
-                f.ty = parse2(wrapped_ty).unwrap();
-
-                let idx = match &f.ident {
-                    Some(id) => ElementIdx::Named(id.clone()),
-                    None => ElementIdx::Unnamed(i),
-                };
-                let variant = variant.ident.clone();
-                elements.insert(VariantElement { variant, idx });
-            }
-            
+            let (idx, member) = match &f.ident {
+                Some(id) => (ElementIdx::Named(id.clone()), Member::Named(id.clone())),
+                None => (ElementIdx::Unnamed(i), Member::Unnamed(i.into())),
+            };
+            elements.insert(Element { path: path.to_vec(), idx });
+            members.push(member);
         }
     }
 
-    elements
+    members
 }
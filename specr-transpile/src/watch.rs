@@ -0,0 +1,81 @@
+//! Watch mode: transpiles again whenever a .md file in the `input` directory changes.
+//!
+//! We detect changes by polling the modification times of the .md files,
+//! so this works on every platform without a file notification service.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::prelude::*;
+use crate::pass::Registry;
+use crate::source;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// the modification time and length of every .md file.
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Runs specr-transpile on every change of the input, forever.
+/// Only diagnostics that were not already reported by the previous run are printed.
+pub fn watch(config: &Config, passes: &Registry) -> ! {
+    let mut last: Option<Snapshot> = None;
+    let mut reported: HashSet<String> = HashSet::new();
+    let mut cache = source::Cache::default();
+
+    loop {
+        let current = snapshot(&config.input_path());
+        if last.as_ref() != Some(&current) {
+            // wait until the files are no longer being written to.
+            thread::sleep(POLL_INTERVAL);
+            let current = snapshot(&config.input_path());
+
+            let outcome = crate::run(config, passes, &mut cache);
+            let mut unchanged = 0;
+            for m in &outcome.messages {
+                if reported.contains(m) {
+                    unchanged += 1;
+                } else {
+                    eprintln!("{m}");
+                }
+            }
+            if let Some(summary) = &outcome.summary {
+                eprintln!("{summary}");
+            }
+            if unchanged > 0 {
+                eprintln!("note: {unchanged} diagnostic(s) unchanged since the last run are not repeated");
+            }
+            let status = if outcome.success { "succeeded" } else { "failed" };
+            eprintln!("[specr-transpile] transpiling {status}; watching `{}` for changes...\n", config.input);
+
+            reported = outcome.messages.into_iter().collect();
+            last = Some(current);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn snapshot(dir: &Path) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        // files might vanish while we look at them, so we ignore all errors here.
+        let Ok(entries) = fs::read_dir(&dir) else { continue };
+        for f in entries.flatten() {
+            let path = f.path();
+            let Ok(meta) = f.metadata() else { continue };
+            if meta.is_dir() {
+                if f.file_name() != ".git" {
+                    dirs.push(path);
+                }
+            } else if path.extension().map_or(false, |e| e == "md") {
+                snapshot.insert(path, (meta.modified().ok(), meta.len()));
+            }
+        }
+    }
+
+    snapshot
+}